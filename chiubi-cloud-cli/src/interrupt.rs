@@ -0,0 +1,35 @@
+use std::process;
+use std::sync::Mutex;
+
+use downloader::CancellationToken;
+
+/// Exit code of a process terminated by SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// The token of the running cancellable operation, if any
+static ACTIVE: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Handles Ctrl-C: the first press cancels the running operation, a second one
+/// or a press while nothing can be cancelled exits immediately
+pub fn install_handler() {
+    ctrlc::set_handler(|| {
+        let active = ACTIVE.lock().unwrap();
+        match active.as_ref() {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                eprintln!("\nCancelling, press Ctrl-C again to exit immediately");
+            }
+            _ => process::exit(INTERRUPTED_EXIT_CODE),
+        }
+    })
+    .expect("Could not install Ctrl-C handler");
+}
+
+/// Runs `operation` with a token that is cancelled by Ctrl-C
+pub fn cancellable<T>(operation: impl FnOnce(&CancellationToken) -> T) -> T {
+    let token = CancellationToken::new();
+    *ACTIVE.lock().unwrap() = Some(token.clone());
+    let result = operation(&token);
+    *ACTIVE.lock().unwrap() = None;
+    result
+}
@@ -1,8 +1,22 @@
+use clap::Parser;
+use std::process;
+
+mod interrupt;
+mod manifest;
+mod queue;
+mod settings;
 mod ui;
 mod tools;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    tools::main::main_menu_interactive();
+    let cli = tools::cli::Cli::parse();
+
+    interrupt::install_handler();
+    tools::remove_temp_files();
+    match cli.command {
+        Some(command) => process::exit(tools::cli::run(command)),
+        None => tools::main::main_menu_interactive(),
+    }
 }
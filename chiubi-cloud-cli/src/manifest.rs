@@ -0,0 +1,75 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Name of the manifest file inside a sync folder
+pub const MANIFEST_FILE_NAME: &str = ".chiubi-sync.toml";
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub file_name: String,
+    pub size: u64,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Id of the track on the Plex server, it survives metadata changes
+    #[serde(default)]
+    pub rating_key: Option<String>,
+    /// Id of the downloaded file on the Plex server, it changes when the file is replaced
+    #[serde(default)]
+    pub part_id: Option<String>,
+}
+
+/// Records the files a sync has downloaded into a folder
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
+pub struct SyncManifest {
+    #[serde(default)]
+    pub files: Vec<ManifestEntry>,
+}
+
+impl SyncManifest {
+    /// Reads the manifest of `dir`, a missing or unreadable manifest is empty
+    pub fn read(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(MANIFEST_FILE_NAME))
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let contents =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        downloader::write_atomically(&dir.join(MANIFEST_FILE_NAME), contents.as_bytes())
+    }
+
+    /// Adds an entry, replacing a previous one for the same file
+    pub fn record(&mut self, entry: ManifestEntry) {
+        self.files.retain(|e| e.file_name != entry.file_name);
+        self.files.push(entry);
+    }
+
+    pub fn remove(&mut self, file_name: &str) {
+        self.files.retain(|e| e.file_name != file_name);
+    }
+
+    /// The entries of a track, a track that is listed more than once has several files
+    pub fn find_tracks<'a>(
+        &'a self,
+        rating_key: &'a str,
+    ) -> impl Iterator<Item = &'a ManifestEntry> + 'a {
+        self.files
+            .iter()
+            .filter(move |e| e.rating_key.as_deref() == Some(rating_key))
+    }
+
+    pub fn find_file(&self, file_name: &str) -> Option<&ManifestEntry> {
+        self.files.iter().find(|e| e.file_name == file_name)
+    }
+
+    /// Updates the entry of a file that was renamed from `from` to `to`
+    pub fn rename(&mut self, from: &str, to: &str) {
+        self.remove(to);
+        if let Some(entry) = self.files.iter_mut().find(|e| e.file_name == from) {
+            entry.file_name = to.into();
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemState {
+    Pending,
+    /// Still set when the CLI was closed during the download
+    InProgress,
+    Done,
+    Failed,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct QueueItem {
+    /// The key of the file on the server, the download URL is built from the current
+    /// configuration so that no token ends up in the queue
+    pub part_key: String,
+    pub path: PathBuf,
+    pub file_name: String,
+    #[serde(default)]
+    pub expected_size: Option<u64>,
+    #[serde(default)]
+    pub rating_key: Option<String>,
+    #[serde(default)]
+    pub part_id: Option<String>,
+    pub state: ItemState,
+    /// How many times the download was started, across sessions
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl QueueItem {
+    pub fn is_finished(&self) -> bool {
+        self.state == ItemState::Done
+    }
+}
+
+/// The planned downloads, persisted so that a later run can continue them
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
+pub struct DownloadQueue {
+    #[serde(default)]
+    pub items: Vec<QueueItem>,
+}
+
+impl DownloadQueue {
+    /// Reads the queue at `path`, a missing or unreadable queue is empty
+    pub fn read(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the queue to `path`, an empty queue removes the file
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if self.items.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let contents =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        downloader::write_atomically(path, contents.as_bytes())
+    }
+
+    /// Adds an item, replacing a previous one for the same path but keeping its attempts
+    pub fn enqueue(&mut self, mut item: QueueItem) {
+        if let Some(previous) = self.items.iter().position(|i| i.path == item.path) {
+            item.attempts += self.items.remove(previous).attempts;
+        }
+        self.items.push(item);
+    }
+
+    pub fn unfinished(&self) -> impl Iterator<Item = &QueueItem> {
+        self.items.iter().filter(|i| !i.is_finished())
+    }
+
+    pub fn count(&self, state: ItemState) -> usize {
+        self.items.iter().filter(|i| i.state == state).count()
+    }
+
+    /// Sets the state of the item for `path`. Starting it counts an attempt, the error of
+    /// the last failure is kept until the download succeeds or fails again.
+    pub fn update(&mut self, path: &Path, state: ItemState, error: Option<String>) {
+        if let Some(item) = self.items.iter_mut().find(|i| i.path == path) {
+            match state {
+                ItemState::InProgress => item.attempts += 1,
+                ItemState::Done | ItemState::Failed => item.last_error = error,
+                ItemState::Pending => {}
+            }
+            item.state = state;
+        }
+    }
+
+    /// Drops the finished downloads, they are recorded in the sync manifests
+    pub fn remove_finished(&mut self) {
+        self.items.retain(|i| !i.is_finished());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use downloader::test_support::TestDir;
+
+    use super::*;
+
+    fn item(file_name: &str) -> QueueItem {
+        QueueItem {
+            part_key: format!("/library/parts/1/{}", file_name),
+            path: PathBuf::from("/music/Road Trip").join(file_name),
+            file_name: file_name.into(),
+            expected_size: Some(10),
+            rating_key: None,
+            part_id: None,
+            state: ItemState::Pending,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn enqueueing_again_keeps_the_attempts() {
+        let mut queue = DownloadQueue::default();
+        queue.enqueue(QueueItem {
+            attempts: 2,
+            state: ItemState::Failed,
+            ..item("01 Intro.mp3")
+        });
+        queue.enqueue(item("02 Outro.mp3"));
+        queue.enqueue(item("01 Intro.mp3"));
+
+        assert_eq!(queue.items.len(), 2);
+        assert_eq!(queue.items[1].file_name, "01 Intro.mp3");
+        assert_eq!(queue.items[1].state, ItemState::Pending);
+        assert_eq!(queue.items[1].attempts, 2);
+    }
+
+    #[test]
+    fn only_starting_a_download_counts_an_attempt() {
+        let mut queue = DownloadQueue::default();
+        queue.enqueue(item("01 Intro.mp3"));
+        let path = queue.items[0].path.clone();
+
+        queue.update(&path, ItemState::InProgress, None);
+        queue.update(&path, ItemState::Failed, Some("timed out".into()));
+        assert_eq!(queue.items[0].attempts, 1);
+        assert_eq!(queue.items[0].last_error.as_deref(), Some("timed out"));
+
+        // The error is kept until the next attempt has finished
+        queue.update(&path, ItemState::Pending, None);
+        queue.update(&path, ItemState::InProgress, None);
+        assert_eq!(queue.items[0].attempts, 2);
+        assert_eq!(queue.items[0].last_error.as_deref(), Some("timed out"));
+
+        queue.update(&path, ItemState::Done, None);
+        assert_eq!(queue.items[0].state, ItemState::Done);
+        assert_eq!(queue.items[0].last_error, None);
+    }
+
+    #[test]
+    fn finished_downloads_are_removed() {
+        let mut queue = DownloadQueue::default();
+        for file_name in ["01 Intro.mp3", "02 Outro.mp3", "03 Encore.mp3"] {
+            queue.enqueue(item(file_name));
+        }
+        let done = queue.items[0].path.clone();
+        let failed = queue.items[1].path.clone();
+        queue.update(&done, ItemState::Done, None);
+        queue.update(&failed, ItemState::Failed, Some("not found".into()));
+
+        queue.remove_finished();
+        let remaining = queue.items.iter().map(|i| i.file_name.as_str());
+        assert_eq!(
+            remaining.collect::<Vec<_>>(),
+            ["02 Outro.mp3", "03 Encore.mp3"]
+        );
+        assert_eq!(queue.unfinished().count(), 2);
+        assert_eq!(queue.count(ItemState::Failed), 1);
+    }
+
+    #[test]
+    fn writing_an_empty_queue_removes_the_file() {
+        let folder = TestDir::new("queue");
+        let path = folder.join("download-queue.toml");
+
+        let mut queue = DownloadQueue::default();
+        queue.enqueue(item("01 Intro.mp3"));
+        queue.write(&path).unwrap();
+        assert_eq!(DownloadQueue::read(&path).items, queue.items);
+
+        queue.update(&queue.items[0].path.clone(), ItemState::Done, None);
+        queue.remove_finished();
+        queue.write(&path).unwrap();
+        assert!(!path.exists());
+        // Nothing to remove either without a file
+        queue.write(&path).unwrap();
+        assert!(DownloadQueue::read(&path).items.is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use downloader::{HttpConfig, RateLimitWindow, RateLimiter, RequestRetry, RetryPolicy};
+
+use crate::tools::playlist_file::PlaylistFormat;
+
+/// Settings of the CLI itself, stored next to the Plex configuration
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Number of tracks downloaded at the same time
+    pub download_workers: usize,
+    /// How often a failed download or request is attempted before giving up
+    pub download_attempts: u32,
+    /// Whether to record a SHA-256 of every synced file in the sync manifest
+    pub record_checksums: bool,
+    /// Combined download limit in bytes per second, unlimited if empty
+    pub rate_limit: Option<u64>,
+    /// Time of day windows overriding `rate_limit`
+    pub rate_limit_schedule: Vec<RateLimitWindow>,
+    /// Whether mirroring moves removed tracks to a trash folder instead of deleting them
+    pub mirror_trash: bool,
+    /// Share of a folder's files mirroring may remove without asking
+    pub mirror_max_removal_percent: u32,
+    /// Playlist files written next to the synchronized tracks, e.g. `["m3u8", "xspf"]`
+    pub playlist_formats: Vec<PlaylistFormat>,
+    /// Parameter values by tool name, e.g. `[tool_defaults.sync-playlist]` with `dest = "..."`
+    pub tool_defaults: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            download_workers: 4,
+            download_attempts: 5,
+            record_checksums: true,
+            rate_limit: None,
+            rate_limit_schedule: vec![],
+            mirror_trash: true,
+            mirror_max_removal_percent: 20,
+            playlist_formats: vec![PlaylistFormat::M3u8],
+            tool_defaults: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn download_options(&self, http: &HttpConfig) -> downloader::DownloadOptions {
+        downloader::DownloadOptions {
+            retry_policy: self.retry_policy(),
+            compute_checksum: self.record_checksums,
+            rate_limiter: self.rate_limiter().map(Arc::new),
+            http: http.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// The settings with `limit` replacing the configured limit and schedule, if it is set
+    pub fn with_rate_limit(self, limit: Option<u64>) -> Self {
+        match limit {
+            Some(limit) => Settings {
+                rate_limit: Some(limit),
+                rate_limit_schedule: vec![],
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// Retries of requests to the Plex server and plex.tv, reported on the terminal
+    pub fn request_retry(&self) -> RequestRetry {
+        RequestRetry {
+            policy: self.retry_policy(),
+            reporter: Some(report_retry),
+        }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.download_attempts,
+            ..Default::default()
+        }
+    }
+
+    fn rate_limiter(&self) -> Option<RateLimiter> {
+        if self.rate_limit.is_none() && self.rate_limit_schedule.is_empty() {
+            return None;
+        }
+        Some(RateLimiter::new(
+            self.rate_limit,
+            self.rate_limit_schedule.clone(),
+        ))
+    }
+}
+
+fn report_retry(attempt: u32, error: &downloader::Error, delay: Duration) {
+    eprintln!(
+        "Request failed (attempt {}: {:?}), retrying in {:.1}s",
+        attempt,
+        error,
+        delay.as_secs_f32()
+    );
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
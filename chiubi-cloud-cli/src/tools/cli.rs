@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use super::playlist_export::ExportFormat;
+use super::{
+    params, playlist_export, playlist_syncer, plex_config, plex_status, print_config,
+    resume_downloads, verify_sync, ToolError, ToolResult,
+};
+
+pub const EXIT_CODES: &str = "Exit codes:
+  0    Success
+  2    Invalid arguments
+  3    Missing or invalid configuration
+  4    Plex server unreachable or access denied
+  5    Playlist not found
+  6    Some downloads failed
+  7    Synchronized files are damaged or missing, or nothing was synchronized
+  8    Files of removed tracks could not or may not be removed
+  9    Files of renamed tracks could not be renamed
+  130  Cancelled with Ctrl-C";
+
+/// Synchronizes Plex music playlists, runs the interactive menu when no command is given
+#[derive(Parser, Debug)]
+#[command(version, after_help = EXIT_CODES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Synchronize a Plex music playlist to a folder
+    Sync {
+        /// Title or id of a playlist, can be repeated
+        #[arg(long = "playlist", required_unless_present = "all")]
+        playlists: Vec<String>,
+        /// Synchronize all playlists
+        #[arg(long, conflicts_with = "playlists")]
+        all: bool,
+        /// Folder to synchronize to, every playlist goes to a subfolder named after it
+        #[arg(long)]
+        dest: Option<PathBuf>,
+        /// Remove files of tracks that are no longer in the playlists
+        #[arg(long)]
+        mirror: bool,
+        /// Remove files even if more than the configured share of a folder would be removed
+        #[arg(long, requires = "mirror")]
+        force_removal: bool,
+        /// Prefix file names with the position of the track in the playlist
+        #[arg(long)]
+        order_prefix: bool,
+        /// Download limit in bytes per second, replacing the configured limits
+        #[arg(long)]
+        rate_limit: Option<u64>,
+    },
+    /// Print the tracks of a playlist
+    Export {
+        /// Title or id of the playlist
+        #[arg(long)]
+        playlist: String,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+    /// Continue downloads that were interrupted or failed
+    Resume {
+        /// Download limit in bytes per second, replacing the configured limits
+        #[arg(long)]
+        rate_limit: Option<u64>,
+    },
+    /// Check a synchronized folder, or the playlist folders in it, for missing or damaged files
+    Verify {
+        #[arg(long)]
+        dest: PathBuf,
+    },
+    /// Check the connection to your Plex instance
+    Status,
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Specify access to your Plex instance
+    Set {
+        #[arg(long)]
+        token: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+        /// Save the configuration even if the server can't be reached with it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print your configuration
+    Show,
+}
+
+fn execute(command: Command) -> ToolResult {
+    match command {
+        Command::Sync {
+            playlists,
+            all,
+            dest,
+            mirror,
+            force_removal,
+            order_prefix,
+            rate_limit,
+        } => {
+            let mut given = HashMap::from([
+                ("all", vec![all.to_string()]),
+                ("mirror", vec![mirror.to_string()]),
+                ("force_removal", vec![force_removal.to_string()]),
+                ("order_prefix", vec![order_prefix.to_string()]),
+            ]);
+            if !all {
+                given.insert("playlists", playlists);
+            }
+            if let Some(dest) = dest {
+                given.insert("dest", vec![dest.to_string_lossy().to_string()]);
+            }
+            if let Some(rate_limit) = rate_limit {
+                given.insert("rate_limit", vec![rate_limit.to_string()]);
+            }
+            params::run(&playlist_syncer::TOOL, given, false)
+        }
+        Command::Export { playlist, format } => {
+            let format = format.to_possible_value().unwrap().get_name().to_string();
+            let given = HashMap::from([("playlist", vec![playlist]), ("format", vec![format])]);
+            params::run(&playlist_export::TOOL, given, false)
+        }
+        Command::Resume { rate_limit } => {
+            let mut given = HashMap::new();
+            if let Some(rate_limit) = rate_limit {
+                given.insert("rate_limit", vec![rate_limit.to_string()]);
+            }
+            params::run(&resume_downloads::TOOL, given, false)
+        }
+        Command::Verify { dest } => {
+            let given = HashMap::from([("dest", vec![dest.to_string_lossy().to_string()])]);
+            params::run(&verify_sync::TOOL, given, false)
+        }
+        Command::Status => params::run(&plex_status::TOOL, HashMap::new(), false),
+        Command::Config(ConfigCommand::Set { token, url, force }) => {
+            let mut given = HashMap::from([("force", vec![force.to_string()])]);
+            if let Some(token) = token {
+                given.insert("token", vec![token]);
+            }
+            if let Some(url) = url {
+                given.insert("url", vec![url]);
+            }
+            params::run(&plex_config::TOOL, given, false)
+        }
+        Command::Config(ConfigCommand::Show) => {
+            params::run(&print_config::TOOL, HashMap::new(), false)
+        }
+    }
+}
+
+/// Runs the command and returns the exit code of the process
+pub fn run(command: Command) -> i32 {
+    match execute(command) {
+        Ok(_) => 0,
+        Err(e) => {
+            match e {
+                ToolError::Cancelled => {}
+                _ => eprintln!("Error: {}", e),
+            }
+            e.exit_code()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["chiubi-cloud-cli"].iter().chain(args))
+    }
+
+    fn error_kind(args: &[&str]) -> Option<ErrorKind> {
+        parse(args).err().map(|e| e.kind())
+    }
+
+    #[test]
+    fn cli_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn no_command_runs_the_menu() {
+        assert!(parse(&[]).unwrap().command.is_none());
+    }
+
+    #[test]
+    fn sync_takes_several_playlists() {
+        let cli = parse(&[
+            "sync",
+            "--playlist",
+            "Mix",
+            "--playlist",
+            "Road Trip",
+            "--mirror",
+            "--force-removal",
+            "--rate-limit",
+            "1000",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Command::Sync {
+                playlists,
+                all,
+                mirror,
+                force_removal,
+                rate_limit,
+                ..
+            }) => {
+                assert_eq!(playlists, ["Mix", "Road Trip"]);
+                assert!(!all && mirror && force_removal);
+                assert_eq!(rate_limit, Some(1000));
+            }
+            other => panic!("expected a sync command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sync_needs_either_playlists_or_all() {
+        assert!(parse(&["sync", "--all"]).is_ok());
+        assert_eq!(
+            error_kind(&["sync", "--all", "--playlist", "Mix"]),
+            Some(ErrorKind::ArgumentConflict)
+        );
+        assert_eq!(
+            error_kind(&["sync"]),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+    }
+
+    #[test]
+    fn force_removal_requires_mirror() {
+        assert_eq!(
+            error_kind(&["sync", "--all", "--force-removal"]),
+            Some(ErrorKind::MissingRequiredArgument)
+        );
+        assert!(parse(&["sync", "--all", "--mirror", "--force-removal"]).is_ok());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(
+            error_kind(&["resume", "--rate-limit", "fast"]),
+            Some(ErrorKind::ValueValidation)
+        );
+        assert_eq!(
+            error_kind(&["export", "--playlist", "Mix", "--format", "pdf"]),
+            Some(ErrorKind::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn exit_codes_are_the_documented_ones() {
+        let documented = EXIT_CODES
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().next().unwrap().parse().unwrap())
+            .collect::<Vec<i32>>();
+        let io_error = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let invalid_settings =
+            toml::from_str::<crate::settings::Settings>("workers = ").unwrap_err();
+        let errors = [
+            (ToolError::Abort, 0),
+            (ToolError::MissingParameter("dest".into()), 2),
+            (
+                ToolError::InvalidParameter("rate_limit".into(), "fast".into()),
+                2,
+            ),
+            (
+                ToolError::ConfigError(plex_config::Error::IoError(io_error())),
+                3,
+            ),
+            (ToolError::NoPlexConfig, 3),
+            (ToolError::InvalidSettings(invalid_settings), 3),
+            (ToolError::ServerCheckFailed, 4),
+            (
+                ToolError::PlexError(plex::Error::RequestFailed(downloader::Error::Cancelled)),
+                4,
+            ),
+            (ToolError::HomeError(plex::home::Error::Unauthorized), 4),
+            (ToolError::NoHomeUsers, 4),
+            (ToolError::PlaylistNotFound("Mix".into()), 5),
+            (ToolError::DownloadsFailed(1), 6),
+            (ToolError::VerificationFailed(1), 7),
+            (ToolError::NoSyncManifest("music".into()), 7),
+            (
+                ToolError::RemovalRefused {
+                    folder: "music".into(),
+                    count: 10,
+                },
+                8,
+            ),
+            (ToolError::RemovalFailed(io_error()), 8),
+            (ToolError::RenameFailed(io_error()), 9),
+            (ToolError::Cancelled, 130),
+        ];
+        for (error, code) in errors {
+            assert_eq!(error.exit_code(), code, "exit code of {:?}", error);
+            assert!(documented.contains(&code), "{} isn't documented", code);
+        }
+    }
+}
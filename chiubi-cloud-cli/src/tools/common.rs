@@ -1,15 +1,16 @@
+use super::ToolError;
+use crate::ui::start_spinner;
 use plex::config::PlexConfig;
 use plex::{Playlist, PlaylistOverview};
-use crate::ui::start_spinner;
 
-pub fn fetch_playlists(config: &PlexConfig) -> Vec<PlaylistOverview> {
+pub fn fetch_playlists(config: &PlexConfig) -> Result<Vec<PlaylistOverview>, ToolError> {
     let spinner = start_spinner("Loading playlists");
-    let playlists = plex::playlists::fetch_all(&config);
+    let playlists = plex::playlists::fetch_all(config);
     spinner.finish_and_clear();
-    return playlists;
+    playlists.map_err(ToolError::PlexError)
 }
 
-pub fn select_playlist(playlists: &Vec<PlaylistOverview>) -> &PlaylistOverview {
+pub fn select_playlist(playlists: &[PlaylistOverview]) -> &PlaylistOverview {
     let question = requestty::Question::raw_select("Select a playlist")
         .choices(playlists.iter().map(|p| p.title.as_str()))
         .build();
@@ -18,9 +19,52 @@ pub fn select_playlist(playlists: &Vec<PlaylistOverview>) -> &PlaylistOverview {
     &playlists[index]
 }
 
-pub fn load_playlist_details(config: &PlexConfig, playlist_overview: &PlaylistOverview) -> Playlist {
+/// Lets the user pick any number of playlists, `selected` ones are checked initially
+pub fn select_playlists(
+    playlists: &[PlaylistOverview],
+    selected: &[PlaylistOverview],
+) -> Vec<PlaylistOverview> {
+    let question = requestty::Question::multi_select("Select playlists")
+        .choices_with_default(
+            playlists
+                .iter()
+                .map(|p| (p.title.as_str(), selected.contains(p))),
+        )
+        .build();
+    let answer = requestty::prompt_one(question).unwrap();
+    answer
+        .as_list_items()
+        .unwrap()
+        .iter()
+        .map(|item| playlists[item.index].clone())
+        .collect()
+}
+
+/// Finds a playlist by its title or its id on the server
+pub fn find_playlist<'a>(
+    playlists: &'a [PlaylistOverview],
+    title_or_id: &str,
+) -> Result<&'a PlaylistOverview, ToolError> {
+    playlists
+        .iter()
+        .find(|p| p.title == title_or_id || p.rating_key() == Some(title_or_id))
+        .or_else(|| {
+            playlists
+                .iter()
+                .find(|p| p.title.eq_ignore_ascii_case(title_or_id))
+        })
+        .ok_or_else(|| ToolError::PlaylistNotFound(title_or_id.into()))
+}
+
+pub fn load_playlist_details(
+    config: &PlexConfig,
+    playlist_overview: &PlaylistOverview,
+) -> Result<Playlist, ToolError> {
     let spinner = start_spinner("Loading playlist information");
     let playlist = playlist_overview.into_detailed_playlist(config);
-    spinner.finish_with_message(format!("Playlist information loaded"));
-    playlist
-}
\ No newline at end of file
+    match playlist {
+        Ok(_) => spinner.finish_with_message("Playlist information loaded"),
+        Err(_) => spinner.finish_and_clear(),
+    }
+    playlist.map_err(ToolError::PlexError)
+}
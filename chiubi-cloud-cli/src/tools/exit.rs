@@ -3,7 +3,8 @@ use super::{ToolDescription, ToolError, ToolResult};
 pub const TOOL: ToolDescription = ToolDescription {
     name: "exit",
     description: "Exit program",
-    execute_interactive: execute,
+    params: &[],
+    execute: |_| execute(),
     is_active: || true,
 };
 
@@ -1,13 +1,21 @@
 use crate::VERSION;
+use std::collections::HashMap;
 
 use super::*;
 
-use super::{exit, playlist_syncer, plex_config, print_config, ToolDescription};
+use super::{
+    exit, params, playlist_syncer, plex_config, plex_home, plex_status, print_config,
+    resume_downloads, verify_sync, ToolDescription,
+};
 
-const MAIN_MENU_TOOLS: [ToolDescription; 5] = [
+const MAIN_MENU_TOOLS: [ToolDescription; 9] = [
     plex_config::TOOL,
+    plex_status::TOOL,
+    plex_home::TOOL,
     playlist_syncer::TOOL,
+    resume_downloads::TOOL,
     playlist_export::TOOL,
+    verify_sync::TOOL,
     print_config::TOOL,
     exit::TOOL,
 ];
@@ -27,12 +35,15 @@ fn print_header() {
 
 pub fn main_menu_interactive() {
     loop {
-        let tool_entries = MAIN_MENU_TOOLS
+        let active_tools = MAIN_MENU_TOOLS
             .iter()
             .filter(|t| {
                 let is_active = t.is_active;
                 is_active()
             })
+            .collect::<Vec<_>>();
+        let tool_entries = active_tools
+            .iter()
             .map(|t| format!("{}: {}", t.name, t.description))
             .collect::<Vec<_>>();
 
@@ -45,6 +56,10 @@ pub fn main_menu_interactive() {
             println!("Plex configuration: ❌");
         }
 
+        if let Some(home_user) = read_config().and_then(|c| c.home_user) {
+            println!("Plex Home user: {}", home_user);
+        }
+
         let question = requestty::Question::select("Tools")
             .choices(tool_entries)
             .build();
@@ -53,14 +68,14 @@ pub fn main_menu_interactive() {
             .as_list_item()
             .expect("Could not process main menu item");
         let tool_index = answer.index;
-        let tool = &MAIN_MENU_TOOLS[tool_index];
-        let tool_function = tool.execute_interactive;
-        let result = tool_function();
+        let tool = active_tools[tool_index];
+        let result = params::run(tool, HashMap::new(), true);
 
         if let Err(e) = result {
             match e {
                 ToolError::Abort => break,
-                _ => println!("Error: {:?}", e),
+                ToolError::Cancelled => {}
+                _ => println!("Error: {}", e),
             }
         }
     }
@@ -0,0 +1,132 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::SyncManifest;
+
+use super::playlist_file;
+
+/// Folder inside a sync folder that removed files are moved to
+pub const TRASH_FOLDER_NAME: &str = ".chiubi-trash";
+
+/// Extensions of the audio files Plex serves, files of earlier syncs without a manifest
+/// entry are only recognized by them
+const AUDIO_EXTENSIONS: [&str; 11] = [
+    "aac", "aif", "aiff", "alac", "flac", "m4a", "mp3", "ogg", "opus", "wav", "wma",
+];
+
+fn has_audio_extension(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Whether the file belongs to the synchronized tracks: it is recorded in the sync manifest
+/// or is an audio file. Other files the user put into the folder, like cover images, hidden
+/// files like the manifest, unfinished downloads and playlist files are left alone.
+pub fn is_track_file(folder: &Path, file_name: &str, manifest: &SyncManifest) -> bool {
+    !file_name.starts_with('.')
+        && !file_name.ends_with(".part")
+        && !playlist_file::is_playlist_file(file_name)
+        && (manifest.find_file(file_name).is_some() || has_audio_extension(file_name))
+        && folder.join(file_name).is_file()
+}
+
+/// Whether removing `count` of `total` files exceeds the share the user allows without asking
+pub fn exceeds_threshold(count: usize, total: usize, max_percent: u32) -> bool {
+    total > 0 && count * 100 > total * max_percent as usize
+}
+
+/// A path in `trash` for the file that doesn't replace a file moved there before,
+/// e.g. `01 Intro (2).mp3` if `01 Intro.mp3` and `01 Intro (1).mp3` are taken
+fn trash_path(trash: &Path, file_name: &str) -> PathBuf {
+    let path = trash.join(file_name);
+    if !path.exists() {
+        return path;
+    }
+    let name = Path::new(file_name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| trash.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Moves the files to the trash folder or deletes them, and removes them from the manifest
+pub fn remove_files(folder: &Path, file_names: &[String], use_trash: bool) -> io::Result<()> {
+    let trash = folder.join(TRASH_FOLDER_NAME);
+    if use_trash && !file_names.is_empty() {
+        fs::create_dir_all(&trash)?;
+    }
+
+    let mut manifest = SyncManifest::read(folder);
+    for file_name in file_names {
+        let path = folder.join(file_name);
+        if use_trash {
+            fs::rename(&path, trash_path(&trash, file_name))?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        manifest.remove(file_name);
+    }
+    manifest.write(folder)
+}
+
+#[cfg(test)]
+mod tests {
+    use downloader::test_support::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn removing_the_allowed_share_is_not_a_threshold_breach() {
+        assert!(!exceeds_threshold(0, 10, 20));
+        assert!(!exceeds_threshold(2, 10, 20));
+        assert!(exceeds_threshold(3, 10, 20));
+    }
+
+    #[test]
+    fn any_removal_exceeds_a_zero_threshold() {
+        assert!(!exceeds_threshold(0, 10, 0));
+        assert!(exceeds_threshold(1, 1000, 0));
+    }
+
+    #[test]
+    fn removing_all_files_stays_within_a_full_threshold() {
+        assert!(!exceeds_threshold(10, 10, 100));
+    }
+
+    #[test]
+    fn empty_folders_never_exceed_the_threshold() {
+        assert!(!exceeds_threshold(0, 0, 0));
+        assert!(!exceeds_threshold(5, 0, 20));
+    }
+
+    #[test]
+    fn trashed_files_keep_the_ones_trashed_before() {
+        let folder = TestDir::new("trash");
+        let trash = folder.join(TRASH_FOLDER_NAME);
+        fs::create_dir_all(&trash).unwrap();
+        fs::write(trash.join("01 Intro.mp3"), "first").unwrap();
+        fs::write(trash.join("01 Intro (1).mp3"), "second").unwrap();
+        fs::write(folder.join("01 Intro.mp3"), "third").unwrap();
+        fs::write(folder.join("02 Outro"), "fourth").unwrap();
+        fs::write(trash.join("02 Outro"), "fifth").unwrap();
+
+        let file_names = ["01 Intro.mp3".to_string(), "02 Outro".to_string()];
+        remove_files(&folder, &file_names, true).unwrap();
+
+        let read = |name: &str| fs::read_to_string(trash.join(name)).unwrap();
+        assert_eq!(read("01 Intro.mp3"), "first");
+        assert_eq!(read("01 Intro (1).mp3"), "second");
+        assert_eq!(read("01 Intro (2).mp3"), "third");
+        assert_eq!(read("02 Outro"), "fifth");
+        assert_eq!(read("02 Outro (1)"), "fourth");
+        assert!(!folder.join("01 Intro.mp3").exists());
+    }
+}
@@ -1,17 +1,31 @@
+use std::fmt::{Display, Formatter};
+
+use crate::settings::Settings;
+use params::{ParamDescription, ToolArgs};
+
+pub mod cli;
 mod common;
 pub mod exit;
 pub mod main;
+mod mirror;
+pub mod params;
 pub mod playlist_export;
+pub mod playlist_file;
 pub mod playlist_syncer;
 pub mod plex_config;
+pub mod plex_home;
+pub mod plex_status;
 pub mod print_config;
+pub mod resume_downloads;
+pub mod verify_sync;
 
 type ToolResult = Result<(), ToolError>;
 
 pub struct ToolDescription {
     name: &'static str,
     description: &'static str,
-    execute_interactive: fn() -> ToolResult,
+    params: &'static [ParamDescription],
+    execute: fn(&ToolArgs) -> ToolResult,
     is_active: fn() -> bool,
 }
 
@@ -20,15 +34,136 @@ pub enum ToolError {
     Abort,
     ConfigError(plex_config::Error),
     NoPlexConfig,
-    DownloadError(downloader::Error),
+    MissingParameter(String),
+    /// Name and value of the parameter
+    InvalidParameter(String, String),
+    /// The server couldn't be reached or the token has no access
+    ServerCheckFailed,
+    PlaylistNotFound(String),
+    DownloadsFailed(usize),
+    /// Mirroring would remove more files than allowed without confirmation
+    RemovalRefused {
+        folder: std::path::PathBuf,
+        count: usize,
+    },
+    RemovalFailed(std::io::Error),
+    RenameFailed(std::io::Error),
+    /// Number of damaged or missing files
+    VerificationFailed(usize),
+    /// The settings file exists but can't be read
+    InvalidSettings(toml::de::Error),
+    /// Neither the folder nor its subfolders were synchronized
+    NoSyncManifest(std::path::PathBuf),
+    /// Cancelled by Ctrl-C, the summary was already printed
+    Cancelled,
+    PlexError(plex::Error),
+    HomeError(plex::home::Error),
+    /// The account isn't part of a Plex Home
+    NoHomeUsers,
+}
+
+impl ToolError {
+    /// Exit code of the command line interface, see `cli::EXIT_CODES`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ToolError::Abort => 0,
+            ToolError::MissingParameter(_) | ToolError::InvalidParameter(_, _) => 2,
+            ToolError::ConfigError(_) | ToolError::NoPlexConfig | ToolError::InvalidSettings(_) => {
+                3
+            }
+            ToolError::ServerCheckFailed
+            | ToolError::PlexError(_)
+            | ToolError::HomeError(_)
+            | ToolError::NoHomeUsers => 4,
+            ToolError::PlaylistNotFound(_) => 5,
+            ToolError::DownloadsFailed(_) => 6,
+            ToolError::VerificationFailed(_) | ToolError::NoSyncManifest(_) => 7,
+            ToolError::RemovalRefused { .. } | ToolError::RemovalFailed(_) => 8,
+            ToolError::RenameFailed(_) => 9,
+            ToolError::Cancelled => crate::interrupt::INTERRUPTED_EXIT_CODE,
+        }
+    }
+}
+
+impl Display for ToolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolError::Abort => write!(f, "Aborted"),
+            ToolError::ConfigError(e) => write!(f, "Could not save the Plex configuration: {}", e),
+            ToolError::NoPlexConfig => {
+                write!(f, "No Plex configuration found, run plex-init first")
+            }
+            ToolError::MissingParameter(name) => write!(f, "Missing value for {}", name),
+            ToolError::InvalidParameter(name, value) => {
+                write!(f, "Invalid value {:?} for {}", value, name)
+            }
+            ToolError::ServerCheckFailed => {
+                write!(
+                    f,
+                    "The Plex server can't be reached or the token has no access"
+                )
+            }
+            ToolError::PlaylistNotFound(playlist) => write!(f, "Playlist {} not found", playlist),
+            ToolError::DownloadsFailed(count) => write!(f, "{} downloads failed", count),
+            ToolError::RemovalRefused { folder, count } => write!(
+                f,
+                "Refused to remove {} files from {:?}, use --force-removal to remove them",
+                count, folder
+            ),
+            ToolError::RemovalFailed(e) => write!(f, "Could not remove files: {}", e),
+            ToolError::RenameFailed(e) => write!(f, "Could not rename files: {}", e),
+            ToolError::VerificationFailed(count) => {
+                write!(f, "{} files are missing or damaged", count)
+            }
+            ToolError::InvalidSettings(e) => write!(f, "Invalid settings: {}", e),
+            ToolError::NoSyncManifest(folder) => {
+                write!(
+                    f,
+                    "Neither {:?} nor its subfolders were synchronized",
+                    folder
+                )
+            }
+            ToolError::Cancelled => write!(f, "Cancelled"),
+            ToolError::PlexError(e) => write!(f, "Plex request failed: {:?}", e),
+            ToolError::HomeError(e) => write!(f, "Plex Home request failed: {:?}", e),
+            ToolError::NoHomeUsers => write!(f, "The Plex account has no Plex Home users"),
+        }
+    }
+}
+
+fn get_config_dir() -> std::path::PathBuf {
+    dirs::config_dir().unwrap().join("chiubi.cloud")
 }
 
 fn get_config_path() -> std::path::PathBuf {
-    let path = dirs::config_dir()
-        .unwrap()
-        .join("chiubi.cloud")
-        .join("conf.toml");
-    path
+    get_config_dir().join("conf.toml")
+}
+
+fn get_settings_path() -> std::path::PathBuf {
+    get_config_dir().join("settings.toml")
+}
+
+fn get_queue_path() -> std::path::PathBuf {
+    get_config_dir().join("download-queue.toml")
+}
+
+/// Removes files left behind by writes that were interrupted by a crash, in the config
+/// directory and the folders of queued downloads
+pub fn remove_temp_files() {
+    let queue = crate::queue::DownloadQueue::read(&get_queue_path());
+    let mut dirs = queue
+        .items
+        .iter()
+        .filter_map(|i| i.path.parent().map(|p| p.to_path_buf()))
+        .collect::<Vec<_>>();
+    dirs.push(get_config_dir());
+    dirs.sort();
+    dirs.dedup();
+
+    for dir in dirs {
+        // Missing folders have nothing to clean up
+        let _ = downloader::remove_temp_files(&dir);
+    }
 }
 
 pub fn is_config_existing() -> bool {
@@ -39,7 +174,7 @@ pub fn is_config_existing() -> bool {
 pub fn read_config() -> Option<plex::config::PlexConfig> {
     let config_content = std::fs::read_to_string(get_config_path());
 
-    if let Err(_) = config_content {
+    if config_content.is_err() {
         return None;
     }
 
@@ -49,7 +184,19 @@ pub fn read_config() -> Option<plex::config::PlexConfig> {
         toml::from_str(&config_contents);
 
     match config {
-        Ok(result) => Some(result),
+        Ok(result) => Some(plex::config::PlexConfig {
+            retry: read_settings().unwrap_or_default().request_retry(),
+            ..result
+        }),
         Err(_) => None,
     }
 }
+
+/// Reads the CLI settings, falling back to the defaults if there are none.
+/// Invalid settings, e.g. a rate limit window with a malformed time, are an error.
+pub fn read_settings() -> Result<Settings, ToolError> {
+    match std::fs::read_to_string(get_settings_path()) {
+        Ok(contents) => toml::from_str(&contents).map_err(ToolError::InvalidSettings),
+        Err(_) => Ok(Settings::default()),
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use plex::PlaylistOverview;
+
+use super::{common, ToolDescription, ToolError};
+use crate::settings::Settings;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    Text,
+    Path,
+    Flag,
+    /// A whole number
+    Number,
+    /// A playlist on the Plex server, given by its title or id
+    Playlist,
+    /// Any number of playlists, each given by its title or id
+    Playlists,
+    /// One of the listed values
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    Text(String),
+    Path(PathBuf),
+    Flag(bool),
+    Number(u64),
+    Playlist(PlaylistOverview),
+    Playlists(Vec<PlaylistOverview>),
+}
+
+pub struct ParamDescription {
+    pub name: &'static str,
+    /// Question asked in the interactive menu, parameters without one are never asked for
+    pub prompt: Option<&'static str>,
+    pub kind: ParamKind,
+    /// Used when the parameter isn't given, may depend on the parameters declared before
+    pub default: fn(&ToolArgs) -> Option<ParamValue>,
+}
+
+/// No default, the parameter must be given
+pub fn no_default(_: &ToolArgs) -> Option<ParamValue> {
+    None
+}
+
+/// The parameters of a tool, resolved to their declared types
+#[derive(Debug, Default)]
+pub struct ToolArgs {
+    /// Whether the tool may prompt, e.g. for confirmations
+    pub interactive: bool,
+    values: HashMap<&'static str, ParamValue>,
+}
+
+impl ToolArgs {
+    pub fn text(&self, name: &str) -> Result<&str, ToolError> {
+        match self.values.get(name) {
+            Some(ParamValue::Text(text)) => Ok(text),
+            _ => Err(ToolError::MissingParameter(name.into())),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> Result<&Path, ToolError> {
+        match self.values.get(name) {
+            Some(ParamValue::Path(path)) => Ok(path),
+            _ => Err(ToolError::MissingParameter(name.into())),
+        }
+    }
+
+    /// Flags that aren't set are false
+    pub fn flag(&self, name: &str) -> bool {
+        matches!(self.values.get(name), Some(ParamValue::Flag(true)))
+    }
+
+    /// Numbers that aren't given are `None`
+    pub fn number(&self, name: &str) -> Option<u64> {
+        match self.values.get(name) {
+            Some(ParamValue::Number(number)) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn playlist(&self, name: &str) -> Result<&PlaylistOverview, ToolError> {
+        match self.values.get(name) {
+            Some(ParamValue::Playlist(playlist)) => Ok(playlist),
+            _ => Err(ToolError::MissingParameter(name.into())),
+        }
+    }
+
+    pub fn playlists(&self, name: &str) -> Result<&[PlaylistOverview], ToolError> {
+        match self.values.get(name) {
+            Some(ParamValue::Playlists(playlists)) => Ok(playlists),
+            _ => Err(ToolError::MissingParameter(name.into())),
+        }
+    }
+}
+
+fn fetch_playlists() -> Result<Vec<PlaylistOverview>, ToolError> {
+    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+    common::fetch_playlists(&config)
+}
+
+/// Converts the values given as text, e.g. command line flags or a config file entry.
+/// Parameters taking a single value use the last one.
+fn parse(param: &ParamDescription, raw: &[String]) -> Result<ParamValue, ToolError> {
+    let last = raw.last().map(|raw| raw.as_str()).unwrap_or_default();
+    let invalid = || ToolError::InvalidParameter(param.name.into(), last.into());
+    match param.kind {
+        ParamKind::Text => Ok(ParamValue::Text(last.into())),
+        ParamKind::Path => Ok(ParamValue::Path(last.into())),
+        ParamKind::Flag => last.parse().map(ParamValue::Flag).map_err(|_| invalid()),
+        ParamKind::Number => last
+            .trim()
+            .parse()
+            .map(ParamValue::Number)
+            .map_err(|_| invalid()),
+        ParamKind::Playlist => {
+            let playlists = fetch_playlists()?;
+            let playlist = common::find_playlist(&playlists, last)?;
+            Ok(ParamValue::Playlist(playlist.clone()))
+        }
+        ParamKind::Playlists => {
+            let playlists = fetch_playlists()?;
+            let selected = raw
+                .iter()
+                .map(|title_or_id| common::find_playlist(&playlists, title_or_id).cloned())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(ParamValue::Playlists(selected))
+        }
+        ParamKind::Choice(choices) => {
+            if choices.contains(&last) {
+                Ok(ParamValue::Text(last.into()))
+            } else {
+                Err(invalid())
+            }
+        }
+    }
+}
+
+fn prompt(
+    param: &ParamDescription,
+    message: &str,
+    default: Option<ParamValue>,
+) -> Result<ParamValue, ToolError> {
+    let text_default = match &default {
+        Some(ParamValue::Text(text)) => text.clone(),
+        Some(ParamValue::Path(path)) => path.to_string_lossy().into(),
+        Some(ParamValue::Number(number)) => number.to_string(),
+        _ => String::new(),
+    };
+
+    let value = match param.kind {
+        ParamKind::Text => {
+            let question = requestty::Question::input(message)
+                .default(text_default)
+                .build();
+            let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+            ParamValue::Text(answer.as_string().unwrap_or_default().into())
+        }
+        ParamKind::Path => {
+            let question = requestty::Question::input(message)
+                .default(text_default)
+                .build();
+            let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+            ParamValue::Path(answer.as_string().unwrap_or_default().into())
+        }
+        ParamKind::Flag => {
+            let question = requestty::Question::confirm(message)
+                .default(default == Some(ParamValue::Flag(true)))
+                .build();
+            let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+            ParamValue::Flag(answer.as_bool().unwrap_or(false))
+        }
+        ParamKind::Number => {
+            let question = requestty::Question::input(message)
+                .default(text_default)
+                .build();
+            let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+            let raw = answer.as_string().unwrap_or_default().to_string();
+            return parse(param, &[raw]);
+        }
+        ParamKind::Playlist => {
+            let playlists = fetch_playlists()?;
+            ParamValue::Playlist(common::select_playlist(&playlists).clone())
+        }
+        ParamKind::Playlists => {
+            let playlists = fetch_playlists()?;
+            let selected = match &default {
+                Some(ParamValue::Playlists(selected)) => selected.clone(),
+                _ => vec![],
+            };
+            ParamValue::Playlists(common::select_playlists(&playlists, &selected))
+        }
+        ParamKind::Choice(choices) => {
+            let question = requestty::Question::select(message)
+                .choices(choices.iter().copied())
+                .build();
+            let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+            let index = answer.as_list_item().map_or(0, |item| item.index);
+            ParamValue::Text(choices[index].into())
+        }
+    };
+    Ok(value)
+}
+
+/// Resolves the parameters of `tool` in the order they are declared. A parameter is taken
+/// from `given`, e.g. command line flags. Otherwise it is prompted for if `interactive` is
+/// set, or its default is used. Tool defaults in `settings` replace the declared defaults.
+pub fn resolve(
+    tool: &ToolDescription,
+    given: HashMap<&str, Vec<String>>,
+    settings: &Settings,
+    interactive: bool,
+) -> Result<ToolArgs, ToolError> {
+    let no_defaults = HashMap::new();
+    let configured = settings
+        .tool_defaults
+        .get(tool.name)
+        .unwrap_or(&no_defaults);
+
+    let mut args = ToolArgs {
+        interactive,
+        values: HashMap::new(),
+    };
+    for param in tool.params {
+        let value = match given.get(param.name) {
+            Some(raw) => Some(parse(param, raw)?),
+            None => {
+                let default = match configured.get(param.name) {
+                    Some(raw) => Some(parse(param, std::slice::from_ref(raw))?),
+                    None => (param.default)(&args),
+                };
+                match (interactive, param.prompt) {
+                    (true, Some(message)) => Some(prompt(param, message, default)?),
+                    _ => default,
+                }
+            }
+        };
+        if let Some(value) = value {
+            args.values.insert(param.name, value);
+        }
+    }
+    Ok(args)
+}
+
+/// Resolves the parameters of `tool` and executes it
+pub fn run(
+    tool: &ToolDescription,
+    given: HashMap<&str, Vec<String>>,
+    interactive: bool,
+) -> Result<(), ToolError> {
+    let settings = super::read_settings()?;
+    let args = resolve(tool, given, &settings, interactive)?;
+    let execute = tool.execute;
+    execute(&args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: [ParamDescription; 3] = [
+        ParamDescription {
+            name: "dest",
+            prompt: Some("Where to?"),
+            kind: ParamKind::Path,
+            default: |_: &ToolArgs| Some(ParamValue::Path("declared".into())),
+        },
+        ParamDescription {
+            name: "format",
+            prompt: None,
+            kind: ParamKind::Choice(&["m3u8", "xspf"]),
+            default: |_: &ToolArgs| Some(ParamValue::Text("m3u8".into())),
+        },
+        ParamDescription {
+            name: "workers",
+            prompt: None,
+            kind: ParamKind::Number,
+            default: no_default,
+        },
+    ];
+
+    const TOOL: ToolDescription = ToolDescription {
+        name: "test-tool",
+        description: "A tool for the tests",
+        params: &PARAMS,
+        execute: |_| Ok(()),
+        is_active: || true,
+    };
+
+    fn parse_one(kind: ParamKind, raw: &str) -> Result<ParamValue, ToolError> {
+        let param = ParamDescription {
+            name: "param",
+            prompt: None,
+            kind,
+            default: no_default,
+        };
+        parse(&param, &[raw.to_string()])
+    }
+
+    fn settings_with_defaults(defaults: &[(&str, &str)]) -> Settings {
+        let defaults = defaults.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        let mut settings = Settings::default();
+        settings
+            .tool_defaults
+            .insert(TOOL.name.into(), defaults.collect());
+        settings
+    }
+
+    #[test]
+    fn values_are_parsed_by_kind() {
+        assert_eq!(
+            parse_one(ParamKind::Flag, "true").unwrap(),
+            ParamValue::Flag(true)
+        );
+        assert_eq!(
+            parse_one(ParamKind::Number, " 42 ").unwrap(),
+            ParamValue::Number(42)
+        );
+        assert_eq!(
+            parse_one(ParamKind::Choice(&["m3u8", "xspf"]), "xspf").unwrap(),
+            ParamValue::Text("xspf".into())
+        );
+        let param = &PARAMS[2];
+        let raw = ["1".to_string(), "2".to_string()];
+        assert_eq!(parse(param, &raw).unwrap(), ParamValue::Number(2));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (kind, raw) in [
+            (ParamKind::Flag, "yes"),
+            (ParamKind::Number, "-1"),
+            (ParamKind::Number, "many"),
+            (ParamKind::Choice(&["m3u8", "xspf"]), "pls"),
+        ] {
+            assert!(matches!(
+                parse_one(kind, raw),
+                Err(ToolError::InvalidParameter(name, value)) if name == "param" && value == raw
+            ));
+        }
+    }
+
+    #[test]
+    fn given_values_replace_tool_defaults_and_declared_defaults() {
+        let settings = settings_with_defaults(&[("dest", "configured"), ("format", "xspf")]);
+        let given = HashMap::from([("dest", vec!["given".to_string()])]);
+        let args = resolve(&TOOL, given, &settings, false).unwrap();
+
+        assert_eq!(args.path("dest").unwrap(), Path::new("given"));
+        assert_eq!(args.text("format").unwrap(), "xspf");
+        assert_eq!(args.number("workers"), None);
+    }
+
+    #[test]
+    fn declared_defaults_are_used_without_tool_defaults() {
+        let args = resolve(&TOOL, HashMap::new(), &Settings::default(), false).unwrap();
+
+        assert_eq!(args.path("dest").unwrap(), Path::new("declared"));
+        assert_eq!(args.text("format").unwrap(), "m3u8");
+    }
+
+    #[test]
+    fn invalid_tool_defaults_are_rejected() {
+        let settings = settings_with_defaults(&[("workers", "many")]);
+        assert!(matches!(
+            resolve(&TOOL, HashMap::new(), &settings, false),
+            Err(ToolError::InvalidParameter(..))
+        ));
+    }
+}
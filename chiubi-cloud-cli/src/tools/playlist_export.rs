@@ -1,24 +1,56 @@
 use crate::tools::common;
+use super::params::{no_default, ParamDescription, ParamKind, ParamValue, ToolArgs};
 use super::{ToolDescription, ToolError};
 
 pub const TOOL: ToolDescription = ToolDescription {
     name: "export-playlist",
     description: "Export playlist to CSV",
-    execute_interactive: playlist_export_interactive,
+    params: &PARAMS,
+    execute: playlist_export_tool,
     is_active: super::is_config_existing,
 };
 
-fn playlist_export_interactive() -> Result<(), ToolError> {
+const PARAMS: [ParamDescription; 2] = [
+    ParamDescription {
+        name: "playlist",
+        prompt: Some("Select a playlist"),
+        kind: ParamKind::Playlist,
+        default: no_default,
+    },
+    ParamDescription {
+        name: "format",
+        prompt: None,
+        kind: ParamKind::Choice(&["csv"]),
+        default: |_: &ToolArgs| Some(ParamValue::Text("csv".into())),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+}
+
+/// Prints the tracks of the playlist in the given format
+fn export_playlist(playlist: &plex::Playlist, format: ExportFormat) {
+    match format {
+        ExportFormat::Csv => {
+            println!("artist;album;title");
+            playlist.tracks.iter().for_each(|track| {
+                println!("{};{};{}", track.artist, track.album, track.title);
+            });
+        }
+    }
+}
+
+fn playlist_export_tool(args: &ToolArgs) -> Result<(), ToolError> {
     let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
 
-    let playlists = common::fetch_playlists(&config);
-    let playlist = common::select_playlist(&playlists);
-    let playlist = common::load_playlist_details(&config, playlist);
+    let format = args.text("format")?;
+    let format = <ExportFormat as clap::ValueEnum>::from_str(format, true)
+        .map_err(|_| ToolError::InvalidParameter("format".into(), format.into()))?;
+    let playlist = common::load_playlist_details(&config, args.playlist("playlist")?)?;
 
-    println!("artist;album;title");
-    playlist.tracks.iter().for_each(|track| {
-        println!("{};{};{}", track.artist, track.album, track.title);
-    });
+    export_playlist(&playlist, format);
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,263 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use sanitize_filename::sanitize;
+
+/// Format of the playlist files written next to the synchronized tracks
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistFormat {
+    /// Extended M3U with UTF-8 encoding
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    const ALL: [PlaylistFormat; 3] = [
+        PlaylistFormat::M3u8,
+        PlaylistFormat::Pls,
+        PlaylistFormat::Xspf,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+/// Whether the file is a playlist file in one of the supported formats
+pub fn is_playlist_file(file_name: &str) -> bool {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    PlaylistFormat::ALL
+        .iter()
+        .any(|f| f.extension().eq_ignore_ascii_case(extension))
+}
+
+/// A track of a playlist file, its file is in the folder of the playlist file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub file_name: String,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    /// Duration in milliseconds
+    pub duration: Option<u64>,
+}
+
+impl PlaylistEntry {
+    pub fn from_track(track: &plex::Track, file_name: String) -> Self {
+        PlaylistEntry {
+            file_name,
+            artist: track.artist.clone(),
+            title: track.title.clone(),
+            album: track.album.clone(),
+            duration: track.duration(),
+        }
+    }
+
+    fn display_title(&self) -> String {
+        format!("{} - {}", self.artist, self.title)
+    }
+
+    /// Duration in whole seconds, -1 if it is unknown
+    fn seconds(&self) -> i64 {
+        self.duration.map_or(-1, |ms| ((ms + 500) / 1000) as i64)
+    }
+}
+
+fn render_m3u8(entries: &[PlaylistEntry]) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+    for entry in entries {
+        let _ = writeln!(
+            contents,
+            "#EXTINF:{},{}",
+            entry.seconds(),
+            entry.display_title()
+        );
+        let _ = writeln!(contents, "{}", entry.file_name);
+    }
+    contents
+}
+
+fn render_pls(entries: &[PlaylistEntry]) -> String {
+    let mut contents = String::from("[playlist]\n");
+    for (index, entry) in entries.iter().enumerate() {
+        let number = index + 1;
+        let _ = writeln!(contents, "File{}={}", number, entry.file_name);
+        let _ = writeln!(contents, "Title{}={}", number, entry.display_title());
+        let _ = writeln!(contents, "Length{}={}", number, entry.seconds());
+    }
+    let _ = writeln!(contents, "NumberOfEntries={}", entries.len());
+    let _ = writeln!(contents, "Version=2");
+    contents
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Percent-encodes a file name for use as a relative URI
+fn encode_uri(file_name: &str) -> String {
+    file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn render_xspf(title: &str, entries: &[PlaylistEntry]) -> String {
+    let mut contents = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    contents.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    let _ = writeln!(contents, "  <title>{}</title>", escape_xml(title));
+    contents.push_str("  <trackList>\n");
+    for entry in entries {
+        contents.push_str("    <track>\n");
+        let _ = writeln!(
+            contents,
+            "      <location>{}</location>",
+            encode_uri(&entry.file_name)
+        );
+        let _ = writeln!(
+            contents,
+            "      <title>{}</title>",
+            escape_xml(&entry.title)
+        );
+        let _ = writeln!(
+            contents,
+            "      <creator>{}</creator>",
+            escape_xml(&entry.artist)
+        );
+        let _ = writeln!(
+            contents,
+            "      <album>{}</album>",
+            escape_xml(&entry.album)
+        );
+        if let Some(duration) = entry.duration {
+            let _ = writeln!(contents, "      <duration>{}</duration>", duration);
+        }
+        contents.push_str("    </track>\n");
+    }
+    contents.push_str("  </trackList>\n</playlist>\n");
+    contents
+}
+
+/// Writes a playlist file named after `title` into `folder` for each format.
+/// The entries are listed in the given order with paths relative to `folder`.
+pub fn write_playlist_files(
+    folder: &Path,
+    title: &str,
+    entries: &[PlaylistEntry],
+    formats: &[PlaylistFormat],
+) -> io::Result<()> {
+    for format in formats {
+        let contents = match format {
+            PlaylistFormat::M3u8 => render_m3u8(entries),
+            PlaylistFormat::Pls => render_pls(entries),
+            PlaylistFormat::Xspf => render_xspf(title, entries),
+        };
+        let file_name = format!("{}.{}", sanitize(title), format.extension());
+        downloader::write_atomically(&folder.join(file_name), contents.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<PlaylistEntry> {
+        vec![
+            PlaylistEntry {
+                file_name: "AC&DC - Back in Black (Live).flac".into(),
+                artist: "AC&DC".into(),
+                title: "Back in Black <Live>".into(),
+                album: "Rock 'n' Roll".into(),
+                duration: Some(255_400),
+            },
+            PlaylistEntry {
+                file_name: "Björk - Jóga.flac".into(),
+                artist: "Björk".into(),
+                title: "Jóga".into(),
+                album: "Homogenic".into(),
+                duration: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn m3u8_lists_relative_paths_with_rounded_durations() {
+        assert_eq!(
+            render_m3u8(&entries()),
+            "#EXTM3U\n\
+             #EXTINF:255,AC&DC - Back in Black <Live>\n\
+             AC&DC - Back in Black (Live).flac\n\
+             #EXTINF:-1,Björk - Jóga\n\
+             Björk - Jóga.flac\n"
+        );
+    }
+
+    #[test]
+    fn pls_numbers_the_entries() {
+        assert_eq!(
+            render_pls(&entries()),
+            "[playlist]\n\
+             File1=AC&DC - Back in Black (Live).flac\n\
+             Title1=AC&DC - Back in Black <Live>\n\
+             Length1=255\n\
+             File2=Björk - Jóga.flac\n\
+             Title2=Björk - Jóga\n\
+             Length2=-1\n\
+             NumberOfEntries=2\n\
+             Version=2\n"
+        );
+    }
+
+    #[test]
+    fn xspf_escapes_text_and_encodes_locations() {
+        assert_eq!(
+            render_xspf("Rock & <Roll>", &entries()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  \
+               <title>Rock &amp; &lt;Roll&gt;</title>\n  \
+               <trackList>\n    \
+                 <track>\n      \
+                   <location>AC%26DC%20-%20Back%20in%20Black%20%28Live%29.flac</location>\n      \
+                   <title>Back in Black &lt;Live&gt;</title>\n      \
+                   <creator>AC&amp;DC</creator>\n      \
+                   <album>Rock &apos;n&apos; Roll</album>\n      \
+                   <duration>255400</duration>\n    \
+                 </track>\n    \
+                 <track>\n      \
+                   <location>Bj%C3%B6rk%20-%20J%C3%B3ga.flac</location>\n      \
+                   <title>Jóga</title>\n      \
+                   <creator>Björk</creator>\n      \
+                   <album>Homogenic</album>\n    \
+                 </track>\n  \
+               </trackList>\n\
+             </playlist>\n"
+        );
+    }
+
+    #[test]
+    fn playlist_files_are_recognized_by_their_extension() {
+        assert!(is_playlist_file("Rock & Roll.m3u8"));
+        assert!(is_playlist_file("Rock & Roll.XSPF"));
+        assert!(!is_playlist_file("AC&DC - Back in Black.flac"));
+    }
+}
@@ -1,37 +1,227 @@
+use crate::interrupt;
+use crate::manifest::{ManifestEntry, SyncManifest};
+use crate::queue::{DownloadQueue, ItemState, QueueItem};
+use crate::settings::Settings;
 use crate::tools::common;
 use dirs::{audio_dir, download_dir, home_dir};
+use downloader::{DownloadOptions, DownloadedFile, IndicatifSink};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use sanitize_filename::sanitize;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, create_dir_all};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
+use super::mirror;
+use super::params::{no_default, ParamDescription, ParamKind, ParamValue, ToolArgs};
+use super::playlist_file::{self, PlaylistEntry};
 use super::{ToolDescription, ToolError};
 
 pub const TOOL: ToolDescription = ToolDescription {
     name: "sync-playlist",
-    description: "Synchronize a Plex music playlist to a folder",
-    execute_interactive: sync_playlist_interactive,
+    description: "Synchronize Plex music playlists to folders",
+    params: &PARAMS,
+    execute: sync_playlist_tool,
     is_active: super::is_config_existing,
 };
 
+const PARAMS: [ParamDescription; 7] = [
+    // Synchronizes every playlist on the server, replacing the selection
+    ParamDescription {
+        name: "all",
+        prompt: None,
+        kind: ParamKind::Flag,
+        default: |_: &ToolArgs| Some(ParamValue::Flag(false)),
+    },
+    ParamDescription {
+        name: "playlists",
+        prompt: Some("Select playlists"),
+        kind: ParamKind::Playlists,
+        default: no_default,
+    },
+    ParamDescription {
+        name: "dest",
+        prompt: Some("Where to synchronize to? Each playlist gets its own folder"),
+        kind: ParamKind::Path,
+        default: |_: &ToolArgs| default_playlist_sync_folder().map(ParamValue::Path),
+    },
+    ParamDescription {
+        name: "mirror",
+        prompt: Some("Remove files of tracks that are no longer in the playlists?"),
+        kind: ParamKind::Flag,
+        default: |_: &ToolArgs| Some(ParamValue::Flag(false)),
+    },
+    ParamDescription {
+        name: "order_prefix",
+        prompt: Some("Prefix file names with their position in the playlist?"),
+        kind: ParamKind::Flag,
+        default: |_: &ToolArgs| Some(ParamValue::Flag(false)),
+    },
+    // Allows mirroring to remove more files than the threshold without asking
+    ParamDescription {
+        name: "force_removal",
+        prompt: None,
+        kind: ParamKind::Flag,
+        default: |_: &ToolArgs| Some(ParamValue::Flag(false)),
+    },
+    RATE_LIMIT_PARAM,
+];
+
+/// Download limit in bytes per second for this run, replacing the configured limits
+pub(super) const RATE_LIMIT_PARAM: ParamDescription = ParamDescription {
+    name: "rate_limit",
+    prompt: None,
+    kind: ParamKind::Number,
+    default: no_default,
+};
+
 #[derive(Debug)]
 pub struct SyncOptions<'a> {
     path: &'a Path,
     playlist_ref: &'a plex::PlaylistOverview,
     config: &'a plex::config::PlexConfig,
+    /// Whether file names start with the position of the track in the playlist
+    order_prefix: bool,
 }
 
+#[derive(Clone)]
 pub struct TrackDownload {
     url: String,
+    part_key: String,
     path: PathBuf,
     file_name: String,
+    expected_size: Option<u64>,
+    rating_key: Option<String>,
+    part_id: Option<String>,
+}
+
+impl From<&TrackDownload> for QueueItem {
+    fn from(download: &TrackDownload) -> Self {
+        QueueItem {
+            part_key: download.part_key.clone(),
+            path: download.path.clone(),
+            file_name: download.file_name.clone(),
+            expected_size: download.expected_size,
+            rating_key: download.rating_key.clone(),
+            part_id: download.part_id.clone(),
+            state: ItemState::Pending,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+}
+
+impl TrackDownload {
+    /// The download of a queued item, from the server in `config`
+    pub fn resume(item: &QueueItem, config: &plex::config::PlexConfig) -> Self {
+        TrackDownload {
+            url: config.get_download_url(&item.part_key),
+            part_key: item.part_key.clone(),
+            path: item.path.clone(),
+            file_name: item.file_name.clone(),
+            expected_size: item.expected_size,
+            rating_key: item.rating_key.clone(),
+            part_id: item.part_id.clone(),
+        }
+    }
+}
+
+/// A track file that gets a new name, e.g. after its title was corrected in Plex
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+}
+
+pub struct SyncPlan {
+    pub downloads: Vec<TrackDownload>,
+    /// Files of tracks that were renamed in Plex, renamed before downloading
+    pub renames: Vec<Rename>,
+    /// Existing files of tracks the manifest doesn't know yet, e.g. from older syncs
+    pub adopted: Vec<ManifestEntry>,
+    /// Track files in the folder that don't belong to the playlist anymore
+    pub obsolete_files: Vec<String>,
+    /// Number of track files in the folder
+    pub existing_track_files: usize,
+    /// The tracks of the playlist in order, with the names of their files
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// Whether the file on the server was replaced since `entry` was downloaded
+fn is_replaced(entry: &ManifestEntry, track: &plex::Track) -> bool {
+    let part_replaced = match (entry.part_id.as_deref(), track.get_part_id()) {
+        (Some(recorded), Some(current)) => recorded != current,
+        _ => false,
+    };
+    let size_changed = track.get_file_size().is_some_and(|size| size != entry.size);
+    part_replaced || size_changed
+}
+
+/// The position and the rest of a file name with a position prefix like "07 - "
+fn split_order_prefix(file_name: &str) -> Option<(usize, &str)> {
+    let digits = file_name.chars().take_while(|c| c.is_ascii_digit()).count();
+    let name = file_name[digits..].strip_prefix(" - ")?;
+    Some((file_name[..digits].parse().ok()?, name))
+}
+
+/// The file name without a position prefix like "07 - "
+fn strip_order_prefix(file_name: &str) -> &str {
+    split_order_prefix(file_name).map_or(file_name, |(_, name)| name)
+}
+
+/// The first of the file names with the prefix of `position`, otherwise the first one
+fn prefer_position<'a>(
+    file_names: impl Iterator<Item = &'a str>,
+    position: usize,
+) -> Option<&'a str> {
+    let file_names = file_names.collect::<Vec<_>>();
+    file_names
+        .iter()
+        .find(|f| split_order_prefix(f).is_some_and(|(p, _)| p == position))
+        .or(file_names.first())
+        .copied()
 }
 
-pub fn prepare_playlist_sync(options: SyncOptions) -> Vec<TrackDownload> {
-    let config = options.config;
+/// The file name with the rating key of the track before the extension, for a track whose
+/// inferred name is taken by another track, e.g. `Artist - Song (1234).flac`
+fn name_with_rating_key(file_name: &str, rating_key: &str) -> String {
+    let name = Path::new(file_name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    match name.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, rating_key, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, rating_key),
+    }
+}
 
-    let tracks = common::load_playlist_details(config, options.playlist_ref).tracks;
+/// Plans the sync of a playlist folder. Tracks are matched to the files of the folder by
+/// their rating key in the sync manifest, so renamed tracks are renamed locally and only
+/// new tracks or replaced files are downloaded. Without a manifest entry the inferred
+/// file name is used. Files of tracks that only moved within the playlist are renamed.
+/// Every file is matched to one entry at most, a track that is listed more than once
+/// keeps the file at its position.
+pub fn prepare_playlist_sync(options: SyncOptions) -> Result<SyncPlan, ToolError> {
+    let tracks = common::load_playlist_details(options.config, options.playlist_ref)?.tracks;
 
-    let existing_files = match fs::read_dir(options.path) {
+    let _ = downloader::remove_temp_files(options.path);
+
+    Ok(plan_playlist_folder(
+        options.path,
+        tracks,
+        options.config,
+        options.order_prefix,
+    ))
+}
+
+/// Plans the sync of the tracks into `folder` from its files and sync manifest
+fn plan_playlist_folder(
+    folder: &Path,
+    tracks: Vec<plex::Track>,
+    config: &plex::config::PlexConfig,
+    order_prefix: bool,
+) -> SyncPlan {
+    let existing_files = match fs::read_dir(folder) {
         Ok(dir) => dir
             .map(|p| {
                 p.expect("Could not read sync dir entry")
@@ -42,97 +232,950 @@ pub fn prepare_playlist_sync(options: SyncOptions) -> Vec<TrackDownload> {
             .collect::<Vec<String>>(),
         Err(_) => vec![],
     };
+    let manifest = SyncManifest::read(folder);
 
-    let file_names = tracks
+    let mut downloads = vec![];
+    let mut renames = vec![];
+    let mut adopted = vec![];
+    let mut entries = vec![];
+    let mut planned = HashSet::new();
+    let width = tracks.len().to_string().len().max(2);
+    // The track each inferred name was given to first, other tracks with the same artist
+    // and title, like a live version, get a name of their own
+    let mut name_owners = HashMap::new();
+    let names = tracks
         .iter()
-        .map(|t| t.infer_file_name())
+        .enumerate()
+        .map(|(index, track)| {
+            let mut base_name = track.infer_file_name();
+            let rating_key = track.rating_key();
+            let owner = name_owners.entry(base_name.clone()).or_insert(rating_key);
+            if *owner != rating_key {
+                let id = rating_key.map_or_else(|| (index + 1).to_string(), String::from);
+                base_name = name_with_rating_key(&base_name, &id);
+            }
+            let file_name = if order_prefix {
+                format!("{:0width$} - {}", index + 1, base_name, width = width)
+            } else {
+                base_name.clone()
+            };
+            (base_name, file_name)
+        })
         .collect::<Vec<_>>();
+    // Files that already have the name of an entry keep it, no other entry takes them over
+    let mut used = names
+        .iter()
+        .map(|(_, file_name)| file_name)
+        .filter(|f| existing_files.contains(f))
+        .cloned()
+        .collect::<HashSet<_>>();
+    let is_free = |used: &HashSet<String>, file_name: &str| {
+        !used.contains(file_name) && existing_files.iter().any(|f| f == file_name)
+    };
+    // Files recorded for a track are only matched by its rating key
+    let is_recorded = |file_name: &str| {
+        manifest
+            .find_file(file_name)
+            .is_some_and(|e| e.rating_key.is_some())
+    };
 
-    let tracks_to_sync: Vec<TrackDownload> = tracks
-        .into_iter()
-        .zip(file_names)
-        .filter(|(_, file_name)| !existing_files.contains(&file_name))
-        .map(|(t, file_name)| TrackDownload {
-            url: t.get_download_url(config),
-            path: options.path.join(file_name.clone()),
-            file_name,
-        })
+    for (index, (track, (base_name, file_name))) in tracks.into_iter().zip(names).enumerate() {
+        if !planned.insert(file_name.clone()) {
+            // The same track listed twice without order prefix, its file is already planned
+            entries.push(PlaylistEntry::from_track(&track, file_name));
+            continue;
+        }
+        let position = index + 1;
+        let rating_key = track.rating_key().map(String::from);
+        let recorded_files = rating_key
+            .as_deref()
+            .into_iter()
+            .flat_map(|key| manifest.find_tracks(key))
+            .map(|entry| entry.file_name.as_str())
+            .filter(|f| is_free(&used, f));
+        let recorded =
+            prefer_position(recorded_files, position).and_then(|f| manifest.find_file(f));
+
+        let replaced = if existing_files.contains(&file_name) {
+            let entry = manifest.find_file(&file_name);
+            match entry {
+                Some(entry) if entry.rating_key.is_some() => is_replaced(entry, &track),
+                _ => {
+                    let path = folder.join(&file_name);
+                    let size = fs::metadata(path).map(|m| m.len()).unwrap_or_default();
+                    // A file of another size, e.g. from an interrupted copy, is downloaded again
+                    let size_differs = track.get_file_size().is_some_and(|s| s != size);
+                    if let (false, Some(rating_key)) = (size_differs, rating_key.clone()) {
+                        adopted.push(ManifestEntry {
+                            file_name: file_name.clone(),
+                            size,
+                            sha256: entry.and_then(|e| e.sha256.clone()),
+                            rating_key: Some(rating_key),
+                            part_id: track.get_part_id().map(String::from),
+                        });
+                    }
+                    size_differs
+                }
+            }
+        } else if let Some(entry) = recorded {
+            used.insert(entry.file_name.clone());
+            renames.push(Rename {
+                from: entry.file_name.clone(),
+                to: file_name.clone(),
+            });
+            is_replaced(entry, &track)
+        } else if let Some(previous) = prefer_position(
+            existing_files
+                .iter()
+                .map(String::as_str)
+                .filter(|f| is_free(&used, f) && !is_recorded(f))
+                .filter(|f| *f == base_name || strip_order_prefix(f) == base_name),
+            position,
+        ) {
+            // The same track at another position, or synchronized with(out) order prefix
+            used.insert(previous.to_string());
+            renames.push(Rename {
+                from: previous.to_string(),
+                to: file_name.clone(),
+            });
+            false
+        } else {
+            true
+        };
+
+        if replaced {
+            downloads.push(TrackDownload {
+                url: track.get_download_url(config),
+                part_key: track.get_parts_key(),
+                path: folder.join(&file_name),
+                file_name: file_name.clone(),
+                expected_size: track.get_file_size(),
+                rating_key,
+                part_id: track.get_part_id().map(String::from),
+            });
+        }
+        entries.push(PlaylistEntry::from_track(&track, file_name));
+    }
+
+    let existing_track_files = existing_files
+        .iter()
+        .filter(|f| mirror::is_track_file(folder, f, &manifest))
+        .cloned()
+        .collect::<Vec<String>>();
+    let obsolete_files = existing_track_files
+        .iter()
+        .filter(|f| !entries.iter().any(|e| &e.file_name == *f))
+        .filter(|f| !renames.iter().any(|r| &r.from == *f))
+        .cloned()
         .collect::<Vec<_>>();
 
-    tracks_to_sync
+    SyncPlan {
+        downloads,
+        renames,
+        adopted,
+        obsolete_files,
+        existing_track_files: existing_track_files.len(),
+        entries,
+    }
+}
+
+#[derive(Debug)]
+pub struct DownloadFailure {
+    pub file_name: String,
+    pub error: downloader::Error,
 }
 
-pub fn perform_download(downloads: Vec<TrackDownload>) -> Result<(), downloader::Error> {
-    for download in downloads {
-        let download_dir = download
+#[derive(Debug)]
+pub struct DownloadReport {
+    pub downloaded: usize,
+    /// Downloads that were interrupted or not started because of a cancellation
+    pub cancelled: usize,
+    pub failures: Vec<DownloadFailure>,
+}
+
+fn download_track(
+    download: &TrackDownload,
+    pb: &ProgressBar,
+    options: &DownloadOptions,
+) -> Result<DownloadedFile, downloader::Error> {
+    let download_dir = download
+        .path
+        .parent()
+        .expect("Could not create download directory");
+
+    create_dir_all(download_dir).map_err(downloader::Error::IoError)?;
+    downloader::download_with_sink(
+        download.path.clone(),
+        download.url.as_str(),
+        Some(download.file_name.as_str()),
+        download.expected_size,
+        &IndicatifSink::new(pb.clone()),
+        options,
+    )
+}
+
+/// Adds the downloaded files to the manifests of their folders
+fn record_in_manifests(downloaded: Vec<(TrackDownload, DownloadedFile)>) {
+    let mut by_folder: HashMap<PathBuf, Vec<ManifestEntry>> = HashMap::new();
+    for (download, file) in downloaded {
+        let folder = download
             .path
             .parent()
-            .expect("Could not create download directory");
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        by_folder.entry(folder).or_default().push(ManifestEntry {
+            file_name: download.file_name,
+            size: file.size,
+            sha256: file.sha256,
+            rating_key: download.rating_key,
+            part_id: download.part_id,
+        });
+    }
 
-        create_dir_all(download_dir).map_err(|e| downloader::Error::IoError(e))?;
-        let download_result = downloader::download_with_progress(
-            download.path,
-            download.url.as_str(),
-            Some(download.file_name.as_str()),
-        );
+    for (folder, entries) in by_folder {
+        let mut manifest = SyncManifest::read(&folder);
+        entries.into_iter().for_each(|e| manifest.record(e));
+        if let Err(e) = manifest.write(&folder) {
+            eprintln!("Could not write sync manifest in {:?}: {:?}", folder, e);
+        }
+    }
+}
+
+/// Updates an item of the persisted download queue
+fn update_queue(
+    queue: &Mutex<DownloadQueue>,
+    queue_path: &Path,
+    path: &Path,
+    state: ItemState,
+    error: Option<String>,
+) {
+    let mut queue = queue.lock().unwrap();
+    queue.update(path, state, error);
+    if let Err(e) = queue.write(queue_path) {
+        eprintln!("Could not write download queue: {:?}", e);
+    }
+}
 
-        if let Err(e) = download_result {
-            return Err(e);
+/// The downloads without those to a path another download already writes to. Concurrent
+/// downloads into the same part file would corrupt it.
+fn unique_downloads(downloads: Vec<TrackDownload>) -> Vec<TrackDownload> {
+    let mut paths = HashSet::new();
+    downloads
+        .into_iter()
+        .filter(|d| paths.insert(d.path.clone()))
+        .collect()
+}
+
+/// Downloads the tracks with `workers` concurrent downloads and records them in the sync
+/// manifest. Failed downloads don't stop the others, they are collected in the report.
+/// Once the cancellation token of `options` is cancelled, no further downloads are started.
+/// The downloads are tracked in the download queue at `queue_path`, so an interrupted run
+/// can be continued later, finished downloads are removed from it at the end.
+pub fn perform_download(
+    downloads: Vec<TrackDownload>,
+    workers: usize,
+    options: &DownloadOptions,
+    queue_path: &Path,
+) -> DownloadReport {
+    let downloads = unique_downloads(downloads);
+    let total = downloads.len();
+    let multi_progress = MultiProgress::new();
+    let overall = multi_progress.add(ProgressBar::new(downloads.len() as u64));
+    overall.set_style(
+        ProgressStyle::default_bar()
+            .template("Total [{elapsed_precise}] [{wide_bar:.green}] {pos}/{len} tracks, {msg}")
+            .progress_chars("#>-"),
+    );
+    overall.set_message(format!("{}", HumanBytes(0)));
+
+    let mut download_queue = DownloadQueue::read(queue_path);
+    downloads
+        .iter()
+        .for_each(|d| download_queue.enqueue(QueueItem::from(d)));
+    let download_queue = Mutex::new(download_queue);
+
+    let pending = Mutex::new(downloads.into_iter());
+    let failures = Mutex::new(vec![]);
+    let downloaded = Mutex::new(vec![]);
+    let downloaded_bytes = AtomicU64::new(0);
+
+    thread::scope(|s| {
+        s.spawn(|| multi_progress.join().expect("Could not draw progress"));
+
+        let worker_handles = (0..workers.max(1))
+            .map(|_| {
+                s.spawn(|| loop {
+                    if options.cancellation.is_cancelled() {
+                        break;
+                    }
+                    let download = match pending.lock().unwrap().next() {
+                        Some(download) => download,
+                        None => break,
+                    };
+
+                    update_queue(
+                        &download_queue,
+                        queue_path,
+                        &download.path,
+                        ItemState::InProgress,
+                        None,
+                    );
+                    let pb = multi_progress.add(ProgressBar::new(0));
+                    match download_track(&download, &pb, options) {
+                        Ok(file) => {
+                            update_queue(
+                                &download_queue,
+                                queue_path,
+                                &download.path,
+                                ItemState::Done,
+                                None,
+                            );
+                            downloaded_bytes.fetch_add(file.size, Ordering::Relaxed);
+                            pb.finish_and_clear();
+                            downloaded.lock().unwrap().push((download, file));
+                        }
+                        Err(downloader::Error::Cancelled) => {
+                            // The part file is kept, the download is resumed next time
+                            update_queue(
+                                &download_queue,
+                                queue_path,
+                                &download.path,
+                                ItemState::Pending,
+                                None,
+                            );
+                            continue;
+                        }
+                        Err(error) => {
+                            let message = Some(format!("{:?}", error));
+                            update_queue(
+                                &download_queue,
+                                queue_path,
+                                &download.path,
+                                ItemState::Failed,
+                                message,
+                            );
+                            failures.lock().unwrap().push(DownloadFailure {
+                                file_name: download.file_name,
+                                error,
+                            });
+                        }
+                    }
+
+                    overall.inc(1);
+                    let bytes = downloaded_bytes.load(Ordering::Relaxed);
+                    overall.set_message(format!("{}", HumanBytes(bytes)));
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in worker_handles {
+            handle.join().expect("Download worker panicked");
         }
+        overall.finish();
+    });
+
+    let downloaded = downloaded.into_inner().unwrap();
+    let downloaded_count = downloaded.len();
+    record_in_manifests(downloaded);
+
+    let mut download_queue = download_queue.into_inner().unwrap();
+    download_queue.remove_finished();
+    if let Err(e) = download_queue.write(queue_path) {
+        eprintln!("Could not write download queue: {:?}", e);
+    }
+
+    let failures = failures.into_inner().unwrap();
+    DownloadReport {
+        downloaded: downloaded_count,
+        cancelled: total - downloaded_count - failures.len(),
+        failures,
     }
+}
+
+/// Runs the downloads with the settings until they are done or cancelled by Ctrl-C
+/// and prints what happened
+pub(super) fn run_downloads(
+    downloads: Vec<TrackDownload>,
+    http: &downloader::HttpConfig,
+    settings: &Settings,
+) -> Result<(), ToolError> {
+    let report = interrupt::cancellable(|cancellation| {
+        let options = DownloadOptions {
+            cancellation: cancellation.clone(),
+            ..settings.download_options(http)
+        };
+        let queue_path = super::get_queue_path();
+        perform_download(downloads, settings.download_workers, &options, &queue_path)
+    });
 
+    println!("{} tracks downloaded", report.downloaded);
+    if !report.failures.is_empty() {
+        println!("{} tracks could not be downloaded:", report.failures.len());
+        for failure in &report.failures {
+            println!("{}: {:?}", failure.file_name, failure.error);
+        }
+    }
+    if report.cancelled > 0 {
+        println!(
+            "Cancelled, {} tracks remain queued, continue them with resume-downloads",
+            report.cancelled
+        );
+        return Err(ToolError::Cancelled);
+    }
+    if !report.failures.is_empty() {
+        return Err(ToolError::DownloadsFailed(report.failures.len()));
+    }
     Ok(())
 }
 
-fn default_playlist_sync_folder() -> Option<PathBuf> {
-    audio_dir()
-        .or_else(|| download_dir())
-        .or_else(|| home_dir())
+pub(super) fn default_playlist_sync_folder() -> Option<PathBuf> {
+    audio_dir().or_else(download_dir).or_else(home_dir)
 }
 
-fn sync_playlist_interactive() -> Result<(), ToolError> {
-    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+/// The folder a playlist is synchronized to below the `root` folder, named after its title.
+/// If another playlist on the server maps to the same folder, the rating key is appended,
+/// so that mirroring one of them doesn't remove the files of the other.
+fn playlist_folder(
+    root: &Path,
+    playlist: &plex::PlaylistOverview,
+    server_playlists: &[plex::PlaylistOverview],
+) -> PathBuf {
+    let name = sanitize(playlist.title.clone());
+    // Compared ignoring case, like the file systems of Windows and macOS do
+    let collides = server_playlists.iter().any(|other| {
+        other.key() != playlist.key()
+            && sanitize(other.title.clone()).to_lowercase() == name.to_lowercase()
+    });
+    if !collides {
+        return root.join(name);
+    }
+    let id = playlist
+        .rating_key()
+        .map(String::from)
+        .unwrap_or_else(|| sanitize(playlist.key()));
+    root.join(format!("{} ({})", name, id))
+}
+
+/// Records files of tracks that were already synchronized in the manifest of `folder`
+fn record_adopted(folder: &Path, adopted: &[ManifestEntry]) {
+    if adopted.is_empty() {
+        return;
+    }
+    let mut manifest = SyncManifest::read(folder);
+    adopted.iter().for_each(|e| manifest.record(e.clone()));
+    if let Err(e) = manifest.write(folder) {
+        eprintln!("Could not write sync manifest in {:?}: {:?}", folder, e);
+    }
+}
+
+/// Renames the files of renamed tracks in `folder` and in its manifest
+fn rename_files(folder: &Path, renames: &[Rename]) -> io::Result<()> {
+    if renames.is_empty() {
+        return Ok(());
+    }
+    let mut manifest = SyncManifest::read(folder);
+    let renamed = renames.iter().try_for_each(|rename| {
+        fs::rename(folder.join(&rename.from), folder.join(&rename.to))?;
+        manifest.rename(&rename.from, &rename.to);
+        Ok(())
+    });
+    // The files renamed before a failure are recorded as well
+    manifest.write(folder)?;
+    renamed
+}
+
+/// Asks whether to remove more files than the settings allow without confirmation.
+/// Without a prompt, the removal is refused unless it is forced.
+fn confirm_removal(folder: &Path, plan: &SyncPlan, interactive: bool) -> Result<(), ToolError> {
+    let refused = ToolError::RemovalRefused {
+        folder: folder.to_path_buf(),
+        count: plan.obsolete_files.len(),
+    };
+    if !interactive {
+        return Err(refused);
+    }
 
-    let playlists = common::fetch_playlists(&config);
-    let selected_playlist = common::select_playlist(&playlists);
-
-    // Destination folder
-    let folder = sanitize(selected_playlist.title.clone());
-    let default_path = default_playlist_sync_folder().unwrap_or_default();
-    let default_path = default_path.join(folder);
-    let default_path = default_path.to_str().unwrap();
-    let question = requestty::Question::input("Where to synchronize to?")
-        .default(default_path)
-        .build();
-    let sync_path = requestty::prompt_one(question);
-    let sync_path = sync_path.unwrap();
-    let sync_path = sync_path.as_string();
-    let sync_path = sync_path.expect("No sync path provided");
-    let sync_path = Path::new(sync_path);
-
-    // Confirmation
-    let options = SyncOptions {
-        path: sync_path,
-        playlist_ref: selected_playlist,
-        config: &config,
+    let question = requestty::Question::confirm(format!(
+        "Really remove {} of {} files from {:?}?",
+        plan.obsolete_files.len(),
+        plan.existing_track_files,
+        folder
+    ))
+    .default(false)
+    .build();
+    match requestty::prompt_one(question).unwrap().as_bool() {
+        Some(true) => Ok(()),
+        _ => Err(refused),
+    }
+}
+
+/// How the playlist folders are synchronized
+struct SyncMode {
+    /// Whether files of tracks that were removed from a playlist are removed as well
+    mirror: bool,
+    /// Whether they are removed even if the settings would ask for confirmation
+    force_removal: bool,
+    /// Whether file names start with the position of the track in the playlist
+    order_prefix: bool,
+}
+
+/// Synchronizes every playlist to its folder in `folders`, after asking for confirmation
+/// if `confirm` is set. All tracks share one download run and summary.
+fn sync_playlists(
+    config: &plex::config::PlexConfig,
+    playlists: &[plex::PlaylistOverview],
+    folders: &[PathBuf],
+    mode: &SyncMode,
+    settings: &Settings,
+    confirm: bool,
+) -> Result<(), ToolError> {
+    let mut plans = vec![];
+    for (playlist, path) in playlists.iter().zip(folders) {
+        let plan = prepare_playlist_sync(SyncOptions {
+            path,
+            playlist_ref: playlist,
+            config,
+            order_prefix: mode.order_prefix,
+        })?;
+
+        let mut summary = format!(
+            "{}: {} tracks need to be downloaded",
+            playlist.title,
+            plan.downloads.len()
+        );
+        if !plan.renames.is_empty() {
+            summary += &format!(", {} files to rename", plan.renames.len());
+        }
+        if mode.mirror {
+            summary += &format!(", {} files to remove", plan.obsolete_files.len());
+        }
+        println!("{}", summary);
+        plans.push((path.clone(), plan));
+    }
+
+    let download_count = plans.iter().map(|(_, p)| p.downloads.len()).sum::<usize>();
+    let removal_count = if mode.mirror {
+        plans.iter().map(|(_, p)| p.obsolete_files.len()).sum()
+    } else {
+        0
     };
+    if playlists.len() > 1 {
+        println!("{} tracks need to be downloaded in total", download_count);
+    }
 
-    let downloads = prepare_playlist_sync(options);
-    println!("{} tracks need to be downloaded", downloads.len());
+    let rename_count = plans.iter().map(|(_, p)| p.renames.len()).sum::<usize>();
+    let has_changes = download_count > 0 || removal_count > 0 || rename_count > 0;
 
-    if downloads.len() == 0 {
+    if has_changes {
+        // Nothing is changed unless all removals are allowed
+        if mode.mirror && !mode.force_removal {
+            for (path, plan) in &plans {
+                let count = plan.obsolete_files.len();
+                let max_percent = settings.mirror_max_removal_percent;
+                if mirror::exceeds_threshold(count, plan.existing_track_files, max_percent) {
+                    confirm_removal(path, plan, confirm)?;
+                }
+            }
+        }
+
+        if confirm {
+            let question = requestty::Question::confirm("Continue?").build();
+            let answer = requestty::prompt_one(question)
+                .unwrap()
+                .as_bool()
+                .unwrap_or(false);
+            if !answer {
+                return Ok(());
+            }
+        }
+    }
+
+    // Files that were found without changes are only recorded as synchronized now as well
+    let result = apply_plans(config, &plans, settings, mode.mirror);
+    // Also written after an interrupted or partly failed run, listing the files present
+    for ((path, plan), playlist) in plans.iter().zip(playlists) {
+        write_playlist_files(path, &playlist.title, &plan.entries, settings);
+    }
+    result
+}
+
+/// Records the adopted files, then renames, removes and downloads the files of the
+/// planned playlist folders
+fn apply_plans(
+    config: &plex::config::PlexConfig,
+    plans: &[(PathBuf, SyncPlan)],
+    settings: &Settings,
+    mirror_mode: bool,
+) -> Result<(), ToolError> {
+    let mut downloads = vec![];
+    for (path, plan) in plans {
+        record_adopted(path, &plan.adopted);
+        rename_files(path, &plan.renames).map_err(ToolError::RenameFailed)?;
+        if mirror_mode && !plan.obsolete_files.is_empty() {
+            mirror::remove_files(path, &plan.obsolete_files, settings.mirror_trash)
+                .map_err(ToolError::RemovalFailed)?;
+        }
+        downloads.extend(plan.downloads.iter().cloned());
+    }
+
+    if downloads.is_empty() {
         return Ok(());
     }
+    run_downloads(downloads, &config.http, settings)
+}
+
+/// Writes the configured playlist files, listing the tracks whose files are present
+fn write_playlist_files(
+    folder: &Path,
+    title: &str,
+    entries: &[PlaylistEntry],
+    settings: &Settings,
+) {
+    if settings.playlist_formats.is_empty() || !folder.is_dir() {
+        return;
+    }
+    let present = entries
+        .iter()
+        .filter(|e| folder.join(&e.file_name).is_file())
+        .cloned()
+        .collect::<Vec<_>>();
+    let formats = &settings.playlist_formats;
+    if let Err(e) = playlist_file::write_playlist_files(folder, title, &present, formats) {
+        eprintln!("Could not write playlist files in {:?}: {:?}", folder, e);
+    }
+}
+
+fn sync_playlist_tool(args: &ToolArgs) -> Result<(), ToolError> {
+    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+    // Folder names depend on all playlists of the server, not only on the selected ones
+    let server_playlists = common::fetch_playlists(&config)?;
+    let playlists = if args.flag("all") {
+        server_playlists.clone()
+    } else {
+        args.playlists("playlists")?.to_vec()
+    };
+    let root = args.path("dest")?;
+    let folders = playlists
+        .iter()
+        .map(|playlist| playlist_folder(root, playlist, &server_playlists))
+        .collect::<Vec<_>>();
+    let mode = SyncMode {
+        mirror: args.flag("mirror"),
+        force_removal: args.flag("force_removal"),
+        order_prefix: args.flag("order_prefix"),
+    };
+    let settings = super::read_settings()?.with_rate_limit(args.number("rate_limit"));
+    sync_playlists(
+        &config,
+        &playlists,
+        &folders,
+        &mode,
+        &settings,
+        args.interactive,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use downloader::test_support::TestDir;
+    use plex::config::PlexConfig;
 
-    let question = requestty::Question::confirm("Continue?").build();
-    let answer = requestty::prompt_one(question)
+    fn track(rating_key: &str, title: &str) -> plex::Track {
+        toml::from_str(&format!(
+            r#"
+            rating_key = "{key}"
+            title = "{title}"
+            album = "Album"
+            artist = "Artist"
+            media = [{{ parts = [{{ id = "{key}", key = "/library/parts/{key}/file.flac", size = 10 }}] }}]
+            "#,
+            key = rating_key,
+            title = title
+        ))
         .unwrap()
-        .as_bool()
-        .unwrap_or(false);
+    }
 
-    if answer {
-        perform_download(downloads).map_err(|e| ToolError::DownloadError(e))?;
+    fn file_names(downloads: &[TrackDownload]) -> Vec<&str> {
+        downloads.iter().map(|d| d.file_name.as_str()).collect()
     }
 
-    Ok(())
+    /// A manifest entry of an intact file of the track, as written by `track`
+    fn recorded(file_name: &str, rating_key: &str) -> ManifestEntry {
+        ManifestEntry {
+            file_name: file_name.into(),
+            size: 10,
+            sha256: None,
+            rating_key: Some(rating_key.into()),
+            part_id: Some(rating_key.into()),
+        }
+    }
+
+    /// Creates the files of the entries in `folder` and records them in its manifest
+    fn synced_folder(name: &str, entries: &[ManifestEntry]) -> TestDir {
+        let folder = TestDir::new(name);
+        for entry in entries {
+            fs::write(folder.join(&entry.file_name), "").unwrap();
+        }
+        let manifest = SyncManifest {
+            files: entries.to_vec(),
+        };
+        manifest.write(&folder).unwrap();
+        folder
+    }
+
+    fn renames(plan: &SyncPlan) -> Vec<(&str, &str)> {
+        let renames = plan.renames.iter();
+        renames.map(|r| (r.from.as_str(), r.to.as_str())).collect()
+    }
+
+    #[test]
+    fn duplicate_track_is_downloaded_once() {
+        let folder = TestDir::new("duplicate");
+        let tracks = vec![track("1", "Song"), track("2", "Other"), track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false);
+
+        assert_eq!(
+            file_names(&plan.downloads),
+            ["Artist - Song.flac", "Artist - Other.flac"]
+        );
+        let entries = plan.entries.iter().map(|e| e.file_name.as_str());
+        assert!(entries.eq([
+            "Artist - Song.flac",
+            "Artist - Other.flac",
+            "Artist - Song.flac"
+        ]));
+    }
+
+    #[test]
+    fn different_tracks_with_the_same_name_get_their_own_files() {
+        let folder = TestDir::new("same-name");
+        let tracks = vec![track("1", "Song"), track("2", "Song"), track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false);
+
+        assert_eq!(
+            file_names(&plan.downloads),
+            ["Artist - Song.flac", "Artist - Song (2).flac"]
+        );
+        let entries = plan.entries.iter().map(|e| e.file_name.as_str());
+        assert!(entries.eq([
+            "Artist - Song.flac",
+            "Artist - Song (2).flac",
+            "Artist - Song.flac"
+        ]));
+    }
+
+    #[test]
+    fn queued_downloads_are_resumed_with_the_current_token() {
+        let folder = TestDir::new("queued");
+        let config = |token: &str| PlexConfig {
+            token: token.into(),
+            url: "http://plex.local".into(),
+            ..Default::default()
+        };
+        let plan = plan_playlist_folder(&folder, vec![track("1", "Song")], &config("old"), false);
+        let item = QueueItem::from(&plan.downloads[0]);
+        let queue = DownloadQueue {
+            items: vec![item.clone()],
+        };
+        assert!(!toml::to_string(&queue).unwrap().contains("old"));
+
+        let download = TrackDownload::resume(&item, &config("new"));
+        assert!(download
+            .url
+            .starts_with("http://plex.local/library/parts/1/file.flac?X-Plex-Token=new&"));
+    }
+
+    #[test]
+    fn cancelled_downloads_are_not_started_and_stay_queued() {
+        let folder = TestDir::new("cancelled");
+        let tracks = vec![track("1", "Song"), track("2", "Other"), track("3", "Third")];
+        let downloads =
+            plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false).downloads;
+        let options = DownloadOptions::default();
+        options.cancellation.cancel();
+        let queue_path = folder.join("queue.toml");
+        let report = perform_download(downloads, 2, &options, &queue_path);
+
+        assert_eq!(report.downloaded, 0);
+        assert_eq!(report.cancelled, 3);
+        assert!(report.failures.is_empty());
+        let queue = DownloadQueue::read(&queue_path);
+        assert_eq!(queue.count(ItemState::Pending), 3);
+        assert!(queue.items.iter().all(|item| item.attempts == 0));
+    }
+
+    #[test]
+    fn unrecorded_files_are_adopted_only_with_the_size_on_the_server() {
+        let folder = TestDir::new("adopt");
+        fs::write(folder.join("Artist - Song.flac"), "0123456789").unwrap();
+        fs::write(folder.join("Artist - Other.flac"), "012").unwrap();
+        let tracks = vec![track("1", "Song"), track("2", "Other")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false);
+
+        let adopted = plan.adopted.iter().map(|e| e.file_name.as_str());
+        assert!(adopted.eq(["Artist - Song.flac"]));
+        assert_eq!(plan.adopted[0].size, 10);
+        assert_eq!(file_names(&plan.downloads), ["Artist - Other.flac"]);
+    }
+
+    #[test]
+    fn downloads_to_the_same_path_are_dropped() {
+        let folder = TestDir::new("unique");
+        let tracks = vec![track("1", "Song"), track("2", "Other")];
+        let mut downloads =
+            plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false).downloads;
+        downloads.push(downloads[0].clone());
+
+        let downloads = unique_downloads(downloads);
+        assert_eq!(
+            file_names(&downloads),
+            ["Artist - Song.flac", "Artist - Other.flac"]
+        );
+    }
+
+    #[test]
+    fn playlists_with_the_same_title_get_their_own_folders() {
+        let playlist = |key: &str, title: &str| -> plex::PlaylistOverview {
+            toml::from_str(&format!(
+                "key = \"/playlists/{key}/items\"\nrating_key = \"{key}\"\ntitle = \"{title}\"",
+                key = key,
+                title = title
+            ))
+            .unwrap()
+        };
+        let playlists = [
+            playlist("1", "Mix"),
+            playlist("2", "mix"),
+            playlist("3", "Favourites"),
+        ];
+        let root = Path::new("music");
+        let folders = playlists
+            .iter()
+            .map(|p| playlist_folder(root, p, &playlists))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            folders,
+            [
+                root.join("Mix (1)"),
+                root.join("mix (2)"),
+                root.join("Favourites")
+            ]
+        );
+    }
+
+    #[test]
+    fn only_position_prefixes_are_stripped() {
+        assert_eq!(strip_order_prefix("07 - A - Song.flac"), "A - Song.flac");
+        assert_eq!(strip_order_prefix("120 - A - Song.flac"), "A - Song.flac");
+        assert_eq!(strip_order_prefix("A - Song.flac"), "A - Song.flac");
+        assert_eq!(strip_order_prefix("99 Balloons.flac"), "99 Balloons.flac");
+        assert_eq!(strip_order_prefix("07-Song.flac"), "07-Song.flac");
+        assert_eq!(strip_order_prefix(" - Song.flac"), " - Song.flac");
+    }
+
+    #[test]
+    fn renamed_duplicate_track_keeps_the_files_at_its_positions() {
+        let folder = synced_folder(
+            "renamed-duplicate",
+            &[
+                recorded("03 - Artist - Old.flac", "1"),
+                recorded("01 - Artist - Old.flac", "1"),
+                recorded("02 - Artist - Other.flac", "2"),
+            ],
+        );
+        let tracks = vec![track("1", "New"), track("2", "Other"), track("1", "New")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), true);
+
+        assert_eq!(
+            renames(&plan),
+            [
+                ("01 - Artist - Old.flac", "01 - Artist - New.flac"),
+                ("03 - Artist - Old.flac", "03 - Artist - New.flac")
+            ]
+        );
+        assert!(plan.downloads.is_empty());
+        assert!(plan.obsolete_files.is_empty());
+    }
+
+    #[test]
+    fn moved_duplicate_track_renames_only_the_file_that_moved() {
+        let folder = synced_folder(
+            "moved-duplicate",
+            &[
+                recorded("01 - Artist - Song.flac", "1"),
+                recorded("02 - Artist - Song.flac", "1"),
+            ],
+        );
+        let tracks = vec![track("2", "Other"), track("1", "Song"), track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), true);
+
+        assert_eq!(
+            renames(&plan),
+            [("01 - Artist - Song.flac", "03 - Artist - Song.flac")]
+        );
+        assert_eq!(file_names(&plan.downloads), ["01 - Artist - Other.flac"]);
+        assert!(plan.obsolete_files.is_empty());
+    }
+
+    #[test]
+    fn unrecorded_file_is_renamed_for_one_entry_only() {
+        let folder = TestDir::new("unrecorded");
+        fs::write(folder.join("Artist - Song.flac"), "").unwrap();
+        let tracks = vec![track("1", "Song"), track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), true);
+
+        assert_eq!(
+            renames(&plan),
+            [("Artist - Song.flac", "01 - Artist - Song.flac")]
+        );
+        assert_eq!(file_names(&plan.downloads), ["02 - Artist - Song.flac"]);
+    }
+
+    #[test]
+    fn file_recorded_for_another_track_is_not_renamed() {
+        let folder = synced_folder("other-track", &[recorded("01 - Artist - Song.flac", "2")]);
+        let tracks = vec![track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false);
+
+        assert!(plan.renames.is_empty());
+        assert_eq!(file_names(&plan.downloads), ["Artist - Song.flac"]);
+        assert_eq!(plan.obsolete_files, ["01 - Artist - Song.flac"]);
+    }
+
+    #[test]
+    fn mirroring_leaves_files_of_the_user_alone() {
+        let folder = synced_folder(
+            "mirror-user-files",
+            &[
+                recorded("Artist - Song.flac", "1"),
+                recorded("Artist - Removed.flac", "2"),
+                recorded("Artist - Removed.lrc", "2"),
+            ],
+        );
+        for file_name in ["cover.jpg", "Thumbs.db", "notes.txt", "Artist - Older.MP3"] {
+            fs::write(folder.join(file_name), "").unwrap();
+        }
+        let tracks = vec![track("1", "Song")];
+        let plan = plan_playlist_folder(&folder, tracks, &PlexConfig::default(), false);
+
+        let mut obsolete = plan.obsolete_files.clone();
+        obsolete.sort();
+        assert_eq!(
+            obsolete,
+            [
+                "Artist - Older.MP3",
+                "Artist - Removed.flac",
+                "Artist - Removed.lrc"
+            ]
+        );
+        assert_eq!(plan.existing_track_files, 4);
+
+        mirror::remove_files(&folder, &plan.obsolete_files, false).unwrap();
+        for file_name in ["Artist - Song.flac", "cover.jpg", "Thumbs.db", "notes.txt"] {
+            assert!(folder.join(file_name).is_file());
+        }
+        assert!(!folder.join("Artist - Older.MP3").exists());
+    }
 }
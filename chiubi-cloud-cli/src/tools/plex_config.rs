@@ -1,4 +1,6 @@
-use std::fs::{self, create_dir_all};
+use std::fs::create_dir_all;
+use crate::ui::start_spinner;
+use super::params::{ParamDescription, ParamKind, ParamValue, ToolArgs};
 use super::{ToolDescription, ToolResult, ToolError};
 
 #[derive(Debug)]
@@ -7,62 +9,110 @@ pub enum Error {
     IoError(std::io::Error),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::SerializationError(e) => write!(f, "{}", e),
+            Error::IoError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 pub const TOOL: ToolDescription = ToolDescription {
     name: "plex-init",
     description: "Specify access to your Plex instance",
-    execute_interactive: plex_config_interactive,
+    params: &PARAMS,
+    execute: plex_config_tool,
     is_active: || true,
 };
 
-fn persist_config(config: &plex::config::PlexConfig) -> Result<(), Error> {
-    let config_contents =
-        toml::to_string(config).map_err(|e| Error::SerializationError(e))?;
+// The current configuration is the default, so that single values can be changed
+const PARAMS: [ParamDescription; 3] = [
+    ParamDescription {
+        name: "token",
+        prompt: Some("Access Token"),
+        kind: ParamKind::Text,
+        default: |_: &ToolArgs| old_config_value(|c| c.token),
+    },
+    ParamDescription {
+        name: "url",
+        prompt: Some("Url"),
+        kind: ParamKind::Text,
+        default: |_: &ToolArgs| old_config_value(|c| c.url),
+    },
+    ParamDescription {
+        name: "force",
+        prompt: None,
+        kind: ParamKind::Flag,
+        default: |_: &ToolArgs| Some(ParamValue::Flag(false)),
+    },
+];
+
+pub(super) fn persist_config(config: &plex::config::PlexConfig) -> Result<(), Error> {
+    let config_contents = toml::to_string(config).map_err(Error::SerializationError)?;
     let config_path = super::get_config_path();
     let config_dir = config_path.parent().unwrap();
-    create_dir_all(config_dir).map_err(|e| Error::IoError(e))?;
-    fs::write(&config_path, config_contents).map_err(|e| Error::IoError(e))?;
+    create_dir_all(config_dir).map_err(Error::IoError)?;
+    downloader::write_atomically(&config_path, config_contents.as_bytes())
+        .map_err(Error::IoError)?;
     Ok(())
 }
 
-fn plex_config_interactive() -> ToolResult {
-    let old_config = super::read_config();
+/// Prints the server status and returns whether the configuration grants access
+fn check_server(config: &plex::config::PlexConfig) -> bool {
+    let spinner = start_spinner("Checking Plex server");
+    let status = plex::server::fetch_status(config);
+    spinner.finish_and_clear();
 
-    let mut input_token_builder = requestty::Question::input("Access Token");
-    if let Some(default_token) = old_config.as_ref().and_then(|c| Some(c.token.clone())) {
-        input_token_builder = input_token_builder.default(default_token);
+    match status {
+        Ok(status) => {
+            println!("{}", status);
+            status.has_access
+        }
+        Err(e) => {
+            eprintln!("Could not reach Plex server!");
+            eprintln!("Error: {:?}", e);
+            false
+        }
     }
+}
 
-    let mut input_url_builder = requestty::Question::input("Url");
-    if let Some(default_url) = old_config.as_ref().and_then(|c| Some(c.url.clone())) {
-        input_url_builder = input_url_builder.default(default_url);
-    }
+/// The value of the saved configuration, the default of a parameter that updates it
+fn old_config_value(value: fn(plex::config::PlexConfig) -> String) -> Option<ParamValue> {
+    super::read_config().map(value).map(ParamValue::Text)
+}
 
-    let questions = vec![
-        input_token_builder.build(),
-        input_url_builder.build(),
-    ];
-    let answers = requestty::prompt(questions).expect("Could not interpret your answers");
+/// Updates the token and url of the configuration, keeping its other settings. A
+/// configuration the server rejects is only saved when `force` is set or confirmed.
+fn set_config(token: &str, url: &str, force: bool, interactive: bool) -> ToolResult {
+    let mut config = super::read_config().unwrap_or_default();
+    config.url = url.into();
+    // The Plex Home user is only kept as long as its token isn't replaced
+    if config.token != token {
+        config.token = token.into();
+        config.account_token = None;
+        config.home_user = None;
+    }
 
-    let config = plex::config::PlexConfig {
-        token: answers
-            .get_key_value("Access Token")
-            .unwrap()
-            .1
-            .as_string()
-            .unwrap()
-            .into(),
-        url: answers
-            .get_key_value("Url")
-            .unwrap()
-            .1
-            .as_string()
+    if !check_server(&config) && !force {
+        if !interactive {
+            return Err(ToolError::ServerCheckFailed);
+        }
+        let question = requestty::Question::confirm("Save configuration anyway?")
+            .default(false)
+            .build();
+        let save_anyway = requestty::prompt_one(question)
             .unwrap()
-            .into(),
-    };
+            .as_bool()
+            .unwrap_or(false);
+        if !save_anyway {
+            return Ok(());
+        }
+    }
 
     let persist_result = persist_config(&config);
 
-    return match persist_result {
+    match persist_result {
         Ok(_) => {
             println!("Config saved: {:?}", super::get_config_path());
             Ok(())
@@ -72,5 +122,14 @@ fn plex_config_interactive() -> ToolResult {
             eprintln!("Error: {:?}", e);
             Err(ToolError::ConfigError(e))
         }
-    };
-}
\ No newline at end of file
+    }
+}
+
+fn plex_config_tool(args: &ToolArgs) -> ToolResult {
+    set_config(
+        args.text("token")?,
+        args.text("url")?,
+        args.flag("force"),
+        args.interactive,
+    )
+}
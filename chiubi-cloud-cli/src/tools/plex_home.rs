@@ -0,0 +1,52 @@
+use crate::tools::{ToolDescription, ToolError};
+use crate::ui::start_spinner;
+
+pub const TOOL: ToolDescription = ToolDescription {
+    name: "plex-home",
+    description: "Switch to another user of your Plex Home",
+    params: &[],
+    execute: |_| switch_home_user_interactive(),
+    is_active: super::is_config_existing,
+};
+
+fn switch_home_user_interactive() -> Result<(), ToolError> {
+    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+
+    let spinner = start_spinner("Loading Plex Home users");
+    let users = plex::home::fetch_users(&config);
+    spinner.finish_and_clear();
+    let users = users.map_err(ToolError::HomeError)?;
+    if users.is_empty() {
+        return Err(ToolError::NoHomeUsers);
+    }
+
+    let question = requestty::Question::raw_select("Select a user")
+        .choices(users.iter().map(|u| {
+            if u.protected {
+                format!("{} (PIN protected)", u.title)
+            } else {
+                u.title.clone()
+            }
+        }))
+        .build();
+    let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+    let index = answer.as_list_item().ok_or(ToolError::Abort)?.index;
+    let user = &users[index];
+
+    let pin = if user.protected {
+        let question = requestty::Question::password("PIN").mask('*').build();
+        let answer = requestty::prompt_one(question).map_err(|_| ToolError::Abort)?;
+        Some(answer.as_string().ok_or(ToolError::Abort)?.to_string())
+    } else {
+        None
+    };
+
+    let spinner = start_spinner("Switching user");
+    let user_config = plex::home::switch_user(&config, user, pin.as_deref());
+    spinner.finish_and_clear();
+    let user_config = user_config.map_err(ToolError::HomeError)?;
+
+    super::plex_config::persist_config(&user_config).map_err(ToolError::ConfigError)?;
+    println!("Switched to {}", user.title);
+    Ok(())
+}
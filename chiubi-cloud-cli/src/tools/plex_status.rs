@@ -0,0 +1,22 @@
+use crate::tools::{ToolDescription, ToolError};
+use crate::ui::start_spinner;
+
+pub const TOOL: ToolDescription = ToolDescription {
+    name: "plex-status",
+    description: "Check the connection to your Plex instance",
+    params: &[],
+    execute: |_| plex_status(),
+    is_active: super::is_config_existing,
+};
+
+fn plex_status() -> Result<(), ToolError> {
+    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+
+    let spinner = start_spinner("Checking Plex server");
+    let status = plex::server::fetch_status(&config);
+    spinner.finish_and_clear();
+
+    let status = status.map_err(ToolError::PlexError)?;
+    println!("{}", status);
+    Ok(())
+}
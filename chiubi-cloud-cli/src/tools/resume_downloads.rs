@@ -0,0 +1,62 @@
+use crate::queue::{DownloadQueue, ItemState};
+use crate::tools::playlist_syncer::{run_downloads, TrackDownload, RATE_LIMIT_PARAM};
+
+use super::{ToolDescription, ToolError};
+
+pub const TOOL: ToolDescription = ToolDescription {
+    name: "resume-downloads",
+    description: "Continue downloads that were interrupted or failed",
+    params: &[RATE_LIMIT_PARAM],
+    execute: |args| resume_downloads(args.number("rate_limit"), args.interactive),
+    is_active: has_unfinished_downloads,
+};
+
+fn has_unfinished_downloads() -> bool {
+    DownloadQueue::read(&super::get_queue_path())
+        .unfinished()
+        .next()
+        .is_some()
+}
+
+/// Continues the unfinished downloads of the queue, after asking if `confirm` is set.
+/// `rate_limit` replaces the configured limits.
+fn resume_downloads(rate_limit: Option<u64>, confirm: bool) -> Result<(), ToolError> {
+    let config = super::read_config().ok_or(ToolError::NoPlexConfig)?;
+    let settings = super::read_settings()?.with_rate_limit(rate_limit);
+    let queue = DownloadQueue::read(&super::get_queue_path());
+
+    println!(
+        "{} downloads pending, {} interrupted, {} failed",
+        queue.count(ItemState::Pending),
+        queue.count(ItemState::InProgress),
+        queue.count(ItemState::Failed)
+    );
+    for item in queue.items.iter().filter(|i| i.state == ItemState::Failed) {
+        println!(
+            "{} (after {} attempts): {}",
+            item.file_name,
+            item.attempts,
+            item.last_error.as_deref().unwrap_or("unknown error")
+        );
+    }
+
+    if queue.unfinished().next().is_none() {
+        return Ok(());
+    }
+    if confirm {
+        let question = requestty::Question::confirm("Continue?").build();
+        let answer = requestty::prompt_one(question)
+            .unwrap()
+            .as_bool()
+            .unwrap_or(false);
+        if !answer {
+            return Ok(());
+        }
+    }
+
+    let downloads = queue
+        .unfinished()
+        .map(|item| TrackDownload::resume(item, &config))
+        .collect::<Vec<_>>();
+    run_downloads(downloads, &config.http, &settings)
+}
@@ -0,0 +1,169 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::{ManifestEntry, SyncManifest, MANIFEST_FILE_NAME};
+use crate::tools::playlist_syncer::default_playlist_sync_folder;
+use crate::ui::start_spinner;
+
+use super::params::{ParamDescription, ParamKind, ParamValue, ToolArgs};
+use super::{ToolDescription, ToolError};
+
+pub const TOOL: ToolDescription = ToolDescription {
+    name: "verify-sync",
+    description: "Check synchronized folders for missing or damaged files",
+    params: &PARAMS,
+    execute: |args| verify_sync(args.path("dest")?),
+    is_active: || true,
+};
+
+const PARAMS: [ParamDescription; 1] = [ParamDescription {
+    name: "dest",
+    prompt: Some("Which folder to verify?"),
+    kind: ParamKind::Path,
+    default: |_: &ToolArgs| default_playlist_sync_folder().map(ParamValue::Path),
+}];
+
+#[derive(Debug)]
+enum Problem {
+    Missing,
+    Truncated { expected: u64, actual: u64 },
+    SizeChanged { expected: u64, actual: u64 },
+    ContentChanged,
+    Unreadable(std::io::Error),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Missing => write!(f, "missing"),
+            Problem::Truncated { expected, actual } => {
+                write!(f, "truncated, {} of {} bytes", actual, expected)
+            }
+            Problem::SizeChanged { expected, actual } => {
+                write!(f, "size changed from {} to {} bytes", expected, actual)
+            }
+            Problem::ContentChanged => write!(f, "content changed"),
+            Problem::Unreadable(e) => write!(f, "unreadable, {}", e),
+        }
+    }
+}
+
+fn verify_file(folder: &Path, entry: &ManifestEntry) -> Option<Problem> {
+    let path = folder.join(&entry.file_name);
+    let size = entry.size;
+    let actual = match fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Some(Problem::Missing),
+    };
+
+    if actual < size {
+        return Some(Problem::Truncated {
+            expected: size,
+            actual,
+        });
+    }
+    if actual != size {
+        return Some(Problem::SizeChanged {
+            expected: size,
+            actual,
+        });
+    }
+
+    let expected_hash = entry.sha256.as_ref()?;
+    match downloader::sha256_file(&path) {
+        Ok(hash) if &hash == expected_hash => None,
+        Ok(_) => Some(Problem::ContentChanged),
+        Err(e) => Some(Problem::Unreadable(e)),
+    }
+}
+
+fn has_manifest(folder: &Path) -> bool {
+    folder.join(MANIFEST_FILE_NAME).is_file()
+}
+
+/// The synchronized folders in `folder`: the folder itself if it has a sync manifest,
+/// otherwise its subfolders that have one, e.g. the playlist folders of the root folder
+fn synced_folders(folder: &Path) -> Vec<PathBuf> {
+    if has_manifest(folder) {
+        return vec![folder.to_path_buf()];
+    }
+    let mut folders = fs::read_dir(folder)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| has_manifest(path))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    folders.sort();
+    folders
+}
+
+/// Checks the files listed in the sync manifest of `folder`, returns the number of problems
+fn verify_folder(folder: &Path) -> usize {
+    let manifest = SyncManifest::read(folder);
+
+    let spinner = start_spinner("Verifying files");
+    let problems = manifest
+        .files
+        .iter()
+        .filter_map(|entry| {
+            verify_file(folder, entry).map(|problem| (entry.file_name.as_str(), problem))
+        })
+        .collect::<Vec<_>>();
+    spinner.finish_and_clear();
+
+    println!(
+        "{:?}: {} of {} files are intact",
+        folder,
+        manifest.files.len() - problems.len(),
+        manifest.files.len()
+    );
+    for (file_name, problem) in &problems {
+        println!("{}: {}", file_name, problem);
+    }
+    problems.len()
+}
+
+/// Checks the synchronized folders in `folder`, see `synced_folders`
+fn verify_sync(folder: &Path) -> Result<(), ToolError> {
+    let folders = synced_folders(folder);
+    if folders.is_empty() {
+        return Err(ToolError::NoSyncManifest(folder.to_path_buf()));
+    }
+
+    match folders
+        .iter()
+        .map(|folder| verify_folder(folder))
+        .sum::<usize>()
+    {
+        0 => Ok(()),
+        count => Err(ToolError::VerificationFailed(count)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use downloader::test_support::TestDir;
+
+    use super::*;
+
+    #[test]
+    fn synced_folders_of_the_root_are_its_playlist_folders() {
+        let root = TestDir::new("verify");
+        for name in ["Road Trip", "Focus", "Not synced"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+        fs::write(root.join("Road Trip").join(MANIFEST_FILE_NAME), "").unwrap();
+        fs::write(root.join("Focus").join(MANIFEST_FILE_NAME), "").unwrap();
+
+        assert_eq!(
+            synced_folders(&root),
+            [root.join("Focus"), root.join("Road Trip")]
+        );
+        assert_eq!(synced_folders(&root.join("Focus")), [root.join("Focus")]);
+        assert!(synced_folders(&root.join("Not synced")).is_empty());
+        assert!(synced_folders(&root.join("Missing")).is_empty());
+    }
+}
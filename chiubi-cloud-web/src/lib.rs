@@ -10,21 +10,31 @@ use serde_json::json;
 use std::thread;
 use sync_wrapper::SyncWrapper;
 
-fn __playlists(config: PlexConfig) -> Vec<plex::PlaylistOverview> {
+fn to_status_code(error: plex::Error) -> StatusCode {
+    match error {
+        plex::Error::RequestFailed(e) if e.status() == Some(401) => StatusCode::UNAUTHORIZED,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn __playlists(config: PlexConfig) -> Result<Vec<plex::PlaylistOverview>, StatusCode> {
     thread::spawn(move || plex::playlists::fetch_all(&config))
         .join()
         .expect("Thread panicked")
+        .map_err(to_status_code)
 }
 
-async fn get_playlists(Query(params): Query<PlexConfig>) -> Json<Vec<serde_json::Value>> {
-    let playlists = __playlists(params);
+async fn get_playlists(
+    Query(params): Query<PlexConfig>,
+) -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
+    let playlists = __playlists(params)?;
 
     let jsons = playlists
         .into_iter()
-        .map(|p| PlaylistOverviewDto::from(p))
+        .map(PlaylistOverviewDto::from)
         .map(|p| json!(p))
         .collect::<Vec<serde_json::Value>>();
-    Json(jsons)
+    Ok(Json(jsons))
 }
 
 async fn get_tracks_of_playlist(
@@ -32,7 +42,7 @@ async fn get_tracks_of_playlist(
     Path(playlist_id): Path<String>,
 ) -> Result<String, StatusCode> {
     // Result<Json<Vec<serde_json::Value>>, StatusCode> {
-    let playlists = __playlists(config.clone());
+    let playlists = __playlists(config.clone())?;
 
     println!("hi");
     let playlist = playlists
@@ -46,7 +56,8 @@ async fn get_tracks_of_playlist(
 
     let playlist = thread::spawn(move || playlist.into_detailed_playlist(&config.clone()))
         .join()
-        .expect("Thread panicked");
+        .expect("Thread panicked")
+        .map_err(to_status_code)?;
 
     let tracks = playlist
         .tracks
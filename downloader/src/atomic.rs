@@ -0,0 +1,131 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const TEMP_PREFIX: &str = ".chiubi-";
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Path of the temporary file `path` is written to, in the same directory so that
+/// it can be renamed into place
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = TEMP_PREFIX.to_string();
+    file_name.push_str(&path.file_name().unwrap_or_default().to_string_lossy());
+    file_name.push_str(TEMP_SUFFIX);
+    path.with_file_name(file_name)
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    file_name.starts_with(TEMP_PREFIX) && file_name.ends_with(TEMP_SUFFIX)
+}
+
+/// Flushes `file` to disk and renames it from `from` to `to`. After a crash, `to` is
+/// either missing, or has its previous or its new contents, but is never half written.
+pub fn persist(file: File, from: &Path, to: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    drop(file);
+    fs::rename(from, to)?;
+    sync_dir(to)
+}
+
+/// Makes the rename durable. Directories can't be opened on all platforms, where they
+/// can't, the rename is left to the file system.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match File::open(dir) {
+        Ok(dir) => dir.sync_all().or(Ok(())),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    let result = File::create(&temp_path)
+        .and_then(|mut file| file.write_all(contents).map(|_| file))
+        .and_then(|file| persist(file, &temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Removes temporary files left in `dir` by writes that were interrupted.
+/// Partial downloads are kept, they are resumed by the next download.
+pub fn remove_temp_files(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_temp_file(&path) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::part_path;
+    use crate::test_support::TestDir;
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file() {
+        let dir = TestDir::new("atomic-write");
+        let path = dir.join("queue.toml");
+        fs::write(&path, "old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(file_names(&dir), ["queue.toml"]);
+    }
+
+    #[test]
+    fn failed_atomic_write_removes_its_temp_file() {
+        let dir = TestDir::new("atomic-write-failed");
+        // A folder can't be replaced by a file
+        let path = dir.join("queue.toml");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("contents"), "").unwrap();
+
+        assert!(write_atomically(&path, b"new").is_err());
+        assert!(!temp_path(&path).exists());
+        assert!(path.is_dir());
+    }
+
+    #[test]
+    fn only_temp_files_are_cleaned_up() {
+        let dir = TestDir::new("temp-files");
+        let song = dir.join("song.flac");
+        for path in [temp_path(&dir.join("queue.toml")), part_path(&song), song] {
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.join(".chiubi-notes.txt"), "").unwrap();
+        fs::create_dir(temp_path(&dir.join("folder"))).unwrap();
+
+        assert_eq!(remove_temp_files(&dir).unwrap(), 1);
+        assert_eq!(
+            file_names(&dir),
+            [
+                ".chiubi-folder.tmp",
+                ".chiubi-notes.txt",
+                "song.flac",
+                "song.flac.part"
+            ]
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+pub(crate) fn hash_reader(reader: &mut impl Read, hasher: &mut Sha256) -> io::Result<()> {
+    let mut buffer = vec![0; crate::COPY_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub(crate) fn to_hex(hasher: Sha256) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+/// Hex encoded SHA-256 of the file at `path`
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    hash_reader(&mut file, &mut hasher)?;
+    Ok(to_hex(hasher))
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::{Certificate, Proxy};
+
+use crate::Error;
+
+/// Settings of the HTTP client used for every request
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub connect_timeout_secs: u64,
+    /// Maximum time to wait for a response or for the next chunk of its body
+    pub read_timeout_secs: u64,
+    /// `http://`, `https://` or `socks5://` proxy URL
+    pub proxy: Option<String>,
+    /// PEM files with additional trusted root certificates
+    pub root_certificates: Vec<PathBuf>,
+    /// Accepts any certificate, e.g. the self-signed one of a server addressed by its IP.
+    /// Only meant for requests to that server.
+    pub accept_invalid_certs: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            proxy: None,
+            root_certificates: vec![],
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> Result<Client, Error> {
+        let mut builder = Client::builder()
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .timeout(Duration::from_secs(self.read_timeout_secs))
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy).map_err(Error::ClientBuildFailed)?;
+            builder = builder.proxy(proxy);
+        }
+
+        for path in &self.root_certificates {
+            let pem = fs::read(path).map_err(Error::IoError)?;
+            let certificate = Certificate::from_pem(&pem).map_err(Error::ClientBuildFailed)?;
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        builder.build().map_err(Error::ClientBuildFailed)
+    }
+}
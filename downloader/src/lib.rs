@@ -1,60 +1,886 @@
-use std::{fs::File, path::PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::blocking::Client;
-use transfer_progress::Transfer;
+use indicatif::ProgressBar;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+
+use partial::PartInfo;
+
+mod atomic;
+mod cancel;
+mod checksum;
+mod http;
+mod partial;
+mod progress;
+mod rate_limit;
+mod retry;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+pub use atomic::{persist, remove_temp_files, temp_path, write_atomically};
+pub use cancel::CancellationToken;
+pub use checksum::sha256_file;
+pub use http::HttpConfig;
+pub use progress::{ChannelSink, IndicatifSink, NoopSink, ProgressEvent, ProgressSink};
+pub use rate_limit::{RateLimitWindow, RateLimiter};
+pub use retry::{RequestRetry, RetryPolicy, RetryReporter};
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Debug)]
 pub enum Error {
+    ClientBuildFailed(reqwest::Error),
     GetRequestFailed(reqwest::Error),
-    ContentLengthNotAvailable,
+    /// 401 or 403, the token is missing or isn't valid for the resource
+    Unauthorized {
+        body: String,
+    },
+    NotFound {
+        body: String,
+    },
+    /// 429
+    RateLimited {
+        body: String,
+    },
+    /// Any 5xx status
+    ServerError {
+        status: u16,
+        body: String,
+    },
+    UnexpectedStatus {
+        status: u16,
+        body: String,
+    },
+    /// The response isn't in the requested format, e.g. an HTML error page
+    UnexpectedContentType {
+        content_type: String,
+        body: String,
+    },
+    /// A local file couldn't be read or written
     IoError(std::io::Error),
+    /// Receiving the body of the response failed, e.g. because the connection was reset
+    BodyReadFailed(std::io::Error),
+    /// Fewer or more bytes were received than announced by the Content-Length
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The downloaded file doesn't have the size the caller expected
+    UnexpectedSize {
+        expected: u64,
+        actual: u64,
+    },
+    /// The `CancellationToken` of the download was cancelled
+    Cancelled,
+}
+
+impl Error {
+    /// The HTTP status code of a response that was rejected
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Unauthorized { .. } => Some(401),
+            Error::NotFound { .. } => Some(404),
+            Error::RateLimited { .. } => Some(429),
+            Error::ServerError { status, .. } | Error::UnexpectedStatus { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    pub retry_policy: RetryPolicy,
+    /// Whether to compute the SHA-256 of downloaded files
+    pub compute_checksum: bool,
+    /// Shared by all downloads using (a clone of) these options
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub http: HttpConfig,
+    /// Stops the download after the current chunk, the part file is kept for resuming
+    pub cancellation: CancellationToken,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadedFile {
+    pub size: u64,
+    /// Hex encoded SHA-256, if it was requested in the `DownloadOptions`
+    pub sha256: Option<String>,
+}
+
+/// Only the start of error responses is kept for diagnosis
+const BODY_SNIPPET_LENGTH: usize = 300;
+
+fn body_snippet(response: Response) -> String {
+    let body = response.text().unwrap_or_default();
+    body.chars().take(BODY_SNIPPET_LENGTH).collect()
+}
+
+/// Turns unsuccessful responses into the matching error
+fn check_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = body_snippet(response);
+    Err(match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::Unauthorized { body },
+        StatusCode::NOT_FOUND => Error::NotFound { body },
+        StatusCode::TOO_MANY_REQUESTS => Error::RateLimited { body },
+        status if status.is_server_error() => Error::ServerError {
+            status: status.as_u16(),
+            body,
+        },
+        status => Error::UnexpectedStatus {
+            status: status.as_u16(),
+            body,
+        },
+    })
+}
+
+/// Checks that the response has the `accept`ed content type, if it states one at all
+fn check_content_type(response: Response, accept: &str) -> Result<Response, Error> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    // Both `application/xml` and `text/xml` are fine when asking for XML
+    let subtype = accept.rsplit('/').next().unwrap_or(accept);
+    match content_type {
+        Some(content_type) if !content_type.contains(subtype) => {
+            Err(Error::UnexpectedContentType {
+                content_type,
+                body: body_snippet(response),
+            })
+        }
+        _ => Ok(response),
+    }
+}
+
+pub fn get_xml_from_url(
+    from_url: String,
+    http: &HttpConfig,
+    retry: &RequestRetry,
+) -> Result<String, Error> {
+    get_from_url(from_url, "application/xml", http, retry)
 }
 
-pub fn get_xml_from_url(from_url: String) -> Result<String, reqwest::Error> {
-    let body = reqwest::blocking::get(from_url)?.text()?;
-    Ok(body)
+/// Sends a GET request asking for the `accept` content type and returns the body of
+/// the response. Unsuccessful responses and responses in another format are errors.
+/// Failed requests and retryable status codes are retried as `retry` says.
+pub fn get_from_url(
+    from_url: String,
+    accept: &str,
+    http: &HttpConfig,
+    retry: &RequestRetry,
+) -> Result<String, Error> {
+    let client = http.build_client()?;
+
+    retry.run(|| {
+        let response = client
+            .get(from_url.as_str())
+            .header(ACCEPT, accept)
+            .send()
+            .map_err(Error::GetRequestFailed)?;
+        let response = check_content_type(check_status(response)?, accept)?;
+        response.text().map_err(Error::GetRequestFailed)
+    })
 }
 
+/// Sends an empty POST request and returns the body of the response.
+/// Unlike GET requests, failed POST requests aren't retried.
+pub fn post_to_url(to_url: String, accept: &str, http: &HttpConfig) -> Result<String, Error> {
+    let response = http
+        .build_client()?
+        .post(to_url)
+        .header(ACCEPT, accept)
+        .send()
+        .map_err(Error::GetRequestFailed)?;
+    let response = check_content_type(check_status(response)?, accept)?;
+    response.text().map_err(Error::GetRequestFailed)
+}
+
+/// Path of the file a download is written to until it is complete
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    path.with_file_name(file_name)
+}
+
+/// Requests `url` from `resume_from` on. With a `validator`, the server sends the whole
+/// file instead if it changed since the validator was received.
+fn request_from(
+    client: &Client,
+    url: &str,
+    resume_from: u64,
+    validator: Option<&str>,
+) -> Result<Response, Error> {
+    let mut request = client.get(url);
+    if resume_from > 0 {
+        request = request.header(RANGE, format!("bytes={}-", resume_from));
+        if let Some(validator) = validator {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+    request.send().map_err(Error::GetRequestFailed)
+}
+
+/// Downloads `url` to `path`. The data is written to a `.part` file first, which is resumed
+/// with a range request if it already exists, and only renamed to `path` once it is complete.
+/// A part file is only resumed by a download of the same source and expected size.
 pub fn download_with_progress(
     path: PathBuf,
     url: &str,
     download_name: Option<&str>,
 ) -> Result<(), Error> {
-    let client = Client::new();
-    let res = client
-        .get(url)
-        .send()
-        .map_err(|e| Error::GetRequestFailed(e))?;
-    let total_size = res
-        .content_length()
-        .ok_or(Error::ContentLengthNotAvailable)?;
+    let sink = IndicatifSink::new(ProgressBar::new(0));
+    let options = DownloadOptions::default();
+    download_with_sink(path, url, download_name, None, &sink, &options)?;
+    Ok(())
+}
+
+/// Like `download_with_progress`, but reports to the given `ProgressSink`.
+/// Failed attempts are retried according to the retry policy of `options`.
+/// `expected_size`, e.g. the size Plex reports for a track, is shown as total size when
+/// the server doesn't send a Content-Length, and the finished file must have this size.
+pub fn download_with_sink(
+    path: PathBuf,
+    url: &str,
+    download_name: Option<&str>,
+    expected_size: Option<u64>,
+    sink: &dyn ProgressSink,
+    options: &DownloadOptions,
+) -> Result<DownloadedFile, Error> {
+    let name = download_name.unwrap_or(url);
+    let result = options.retry_policy.run_cancellable(
+        &options.cancellation,
+        |attempt, error, delay| sink.retrying(attempt, error, delay),
+        || download_attempt(&path, url, name, expected_size, sink, options),
+    );
+
+    match &result {
+        Ok(file) => sink.finished(file.size),
+        Err(e) => sink.failed(e),
+    }
+    result
+}
+
+/// Compares the size of a finished download with the announced Content-Length and
+/// the size the caller expected
+fn check_size(
+    actual_size: u64,
+    content_length: Option<u64>,
+    expected_size: Option<u64>,
+) -> Result<(), Error> {
+    if let Some(expected) = content_length.filter(|&expected| expected != actual_size) {
+        return Err(Error::SizeMismatch {
+            expected,
+            actual: actual_size,
+        });
+    }
+    if let Some(expected) = expected_size.filter(|&expected| expected != actual_size) {
+        return Err(Error::UnexpectedSize {
+            expected,
+            actual: actual_size,
+        });
+    }
+    Ok(())
+}
+
+fn download_attempt(
+    path: &Path,
+    url: &str,
+    name: &str,
+    expected_size: Option<u64>,
+    sink: &dyn ProgressSink,
+    options: &DownloadOptions,
+) -> Result<DownloadedFile, Error> {
+    if options.cancellation.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    let part_path = part_path(path);
+    let info = PartInfo::new(url, expected_size);
+    // A part file without info, e.g. from an older version of the track, isn't trusted
+    let stored = PartInfo::read(&part_path).filter(|stored| stored.is_same_source(&info));
+    if stored.is_none() {
+        partial::discard(&part_path).map_err(Error::IoError)?;
+    }
+    let mut resume_from = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+    let validator = stored.as_ref().and_then(|s| s.validator.as_deref());
+
+    let client = options.http.build_client()?;
+    let mut res = request_from(&client, url, resume_from, validator)?;
+
+    // The part file is already complete or doesn't belong to this download, start over
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        partial::discard(&part_path).map_err(Error::IoError)?;
+        resume_from = 0;
+        res = request_from(&client, url, resume_from, None)?;
+    }
+
+    let mut res = check_status(res)?;
+
+    // Servers without range support, or with a changed file, send the whole file again
+    let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+    if !resumed {
+        info.with_validator_of(&res)
+            .write(&part_path)
+            .map_err(Error::IoError)?;
+    }
+    let offset = if resumed { resume_from } else { 0 };
+    // Chunked responses, e.g. transcoded streams, have no Content-Length
+    let content_length = res.content_length().map(|length| offset + length);
+
+    sink.started(name, content_length.or(expected_size), offset);
+
+    let mut hasher = options.compute_checksum.then(Sha256::new);
+    if let (true, Some(hasher)) = (resumed, hasher.as_mut()) {
+        let mut existing = File::open(&part_path).map_err(Error::IoError)?;
+        checksum::hash_reader(&mut existing, hasher).map_err(Error::IoError)?;
+    }
+
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(&part_path)
+    } else {
+        File::create(&part_path)
+    }
+    .map_err(Error::IoError)?;
+    let rate_limiter = options.rate_limiter.as_deref();
+    copy_with_progress(
+        &mut res,
+        &mut file,
+        sink,
+        hasher.as_mut(),
+        rate_limiter,
+        &options.cancellation,
+        content_length.is_some(),
+    )?;
+
+    let actual_size = fs::metadata(&part_path).map_err(Error::IoError)?.len();
+    check_size(actual_size, content_length, expected_size)?;
+
+    atomic::persist(file, &part_path, path).map_err(Error::IoError)?;
+    partial::discard(&part_path).map_err(Error::IoError)?;
+
+    Ok(DownloadedFile {
+        size: actual_size,
+        sha256: hasher.map(checksum::to_hex),
+    })
+}
+
+/// A successful download response whose body can be read as it arrives
+pub struct DownloadStream {
+    response: Response,
+}
+
+impl DownloadStream {
+    /// The size of the body, unless the response is chunked
+    pub fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
+    }
+}
+
+impl Read for DownloadStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.response.read(buf)
+    }
+}
+
+/// Sends the request for `url` and returns the body as a stream instead of writing it to a
+/// file. Sending the request is retried according to `options`, reading the stream is not.
+/// The rate limiter of `options` isn't applied, as the caller decides how fast to read.
+pub fn open_stream(url: &str, options: &DownloadOptions) -> Result<DownloadStream, Error> {
+    let client = options.http.build_client()?;
+    let response = options.retry_policy.run_cancellable(
+        &options.cancellation,
+        |_, _, _| {},
+        || check_status(request_from(&client, url, 0, None)?),
+    )?;
+    Ok(DownloadStream { response })
+}
+
+/// Streams `url` into `writer`, e.g. to pipe a track into another program or to keep it in
+/// memory. Nothing is written to disk, so unlike `download_with_sink` an interrupted download
+/// can't be resumed, and only sending the request is retried.
+pub fn download_to_writer(
+    writer: &mut impl Write,
+    url: &str,
+    download_name: Option<&str>,
+    expected_size: Option<u64>,
+    sink: &dyn ProgressSink,
+    options: &DownloadOptions,
+) -> Result<DownloadedFile, Error> {
+    let name = download_name.unwrap_or(url);
+    let result = open_stream(url, options).and_then(|mut stream| {
+        stream_attempt(&mut stream, writer, name, expected_size, sink, options)
+    });
+
+    match &result {
+        Ok(file) => sink.finished(file.size),
+        Err(e) => sink.failed(e),
+    }
+    result
+}
+
+fn stream_attempt(
+    stream: &mut DownloadStream,
+    writer: &mut impl Write,
+    name: &str,
+    expected_size: Option<u64>,
+    sink: &dyn ProgressSink,
+    options: &DownloadOptions,
+) -> Result<DownloadedFile, Error> {
+    let content_length = stream.content_length();
+    sink.started(name, content_length.or(expected_size), 0);
+
+    let mut hasher = options.compute_checksum.then(Sha256::new);
+    let rate_limiter = options.rate_limiter.as_deref();
+    let actual_size = copy_with_progress(
+        stream,
+        writer,
+        sink,
+        hasher.as_mut(),
+        rate_limiter,
+        &options.cancellation,
+        content_length.is_some(),
+    )?;
+
+    check_size(actual_size, content_length, expected_size)?;
+
+    Ok(DownloadedFile {
+        size: actual_size,
+        sha256: hasher.map(checksum::to_hex),
+    })
+}
+
+/// Copies all data from `reader` to `writer`, reporting every written chunk to `sink`
+/// and feeding it to `hasher`. Throughput is throttled by `rate_limiter`, and the copy
+/// stops with `Error::Cancelled` after the current chunk once `cancellation` is cancelled.
+/// If the body has a Content-Length, a connection closed before all of it was received
+/// ends the copy as well, the size check of the caller reports it as `SizeMismatch`.
+/// Errors reading `reader` are `BodyReadFailed`, errors writing `writer` are `IoError`.
+fn copy_with_progress(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    sink: &dyn ProgressSink,
+    mut hasher: Option<&mut Sha256>,
+    rate_limiter: Option<&RateLimiter>,
+    cancellation: &CancellationToken,
+    has_content_length: bool,
+) -> Result<u64, Error> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+
+    loop {
+        if cancellation.is_cancelled() {
+            writer.flush().map_err(Error::IoError)?;
+            return Err(Error::Cancelled);
+        }
+
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if has_content_length && is_incomplete_body(&e) => break,
+            Err(e) => return Err(Error::BodyReadFailed(e)),
+        };
+        writer.write_all(&buffer[..read]).map_err(Error::IoError)?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buffer[..read]);
+        }
+        copied += read as u64;
+        sink.bytes(read as u64);
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire(read as u64);
+        }
+    }
+
+    writer.flush().map_err(Error::IoError)?;
+    Ok(copied)
+}
+
+/// Whether the connection was closed before the whole body was received. The HTTP client
+/// reports this as an `UnexpectedEof` error, wrapped in its own error.
+fn is_incomplete_body(error: &io::Error) -> bool {
+    let mut source = error
+        .get_ref()
+        .map(|e| e as &(dyn std::error::Error + 'static));
+    while let Some(error) = source {
+        match error.downcast_ref::<io::Error>() {
+            Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => return true,
+            _ => source = error.source(),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serve_once, TestDir};
 
-    // Indicatif setup
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-        .progress_chars("#>-"));
+    /// A server on localhost that answers a single request with `response`, returns its URL
+    fn serve(response: String) -> String {
+        let (address, _) = serve_once(response);
+        format!("http://{}/", address)
+    }
 
-    if let Some(download_name) = download_name {
-        pb.set_message(format!("Downloading {}", download_name));
+    /// A response of the local server with the given status, content type and body
+    fn response(status: &str, content_type: Option<&str>, body: &str) -> Response {
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            body.len()
+        );
+        if let Some(content_type) = content_type {
+            response += &format!("Content-Type: {}\r\n", content_type);
+        }
+        response += "\r\n";
+        response += body;
+        Client::new().get(serve(response)).send().unwrap()
     }
 
-    // Download
-    let file = File::create(path).map_err(|e| Error::IoError(e))?;
-    let transfer = Transfer::new(res, file);
+    #[test]
+    fn statuses_map_to_errors() {
+        let status_error = |status: &str| check_status(response(status, None, "denied")).err();
+        assert!(matches!(
+            status_error("401 Unauthorized"),
+            Some(Error::Unauthorized { body }) if body == "denied"
+        ));
+        assert!(matches!(
+            status_error("403 Forbidden"),
+            Some(Error::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            status_error("404 Not Found"),
+            Some(Error::NotFound { .. })
+        ));
+        assert!(matches!(
+            status_error("429 Too Many Requests"),
+            Some(Error::RateLimited { .. })
+        ));
+        assert!(matches!(
+            status_error("500 Internal Server Error"),
+            Some(Error::ServerError { status: 500, .. })
+        ));
+        assert!(matches!(
+            status_error("503 Service Unavailable"),
+            Some(Error::ServerError { status: 503, .. })
+        ));
+        assert!(matches!(
+            status_error("418 I'm a teapot"),
+            Some(Error::UnexpectedStatus { status: 418, .. })
+        ));
+        assert!(status_error("200 OK").is_none());
+    }
 
-    while !transfer.is_complete() {
-        pb.set_position(transfer.transferred());
+    #[test]
+    fn body_snippet_is_capped() {
+        let body = "x".repeat(BODY_SNIPPET_LENGTH * 2);
+        match check_status(response("500 Internal Server Error", None, &body)) {
+            Err(Error::ServerError { body, .. }) => assert_eq!(body.len(), BODY_SNIPPET_LENGTH),
+            other => panic!("expected a server error, got {:?}", other),
+        }
     }
 
-    let (_reader, _writer) = transfer.finish().map_err(|e| Error::IoError(e))?;
+    #[test]
+    fn html_body_is_an_unexpected_content_type() {
+        let html = "<html><body>Sign in</body></html>";
+        let response = response("200 OK", Some("text/html; charset=utf-8"), html);
+        match check_content_type(response, "application/xml") {
+            Err(Error::UnexpectedContentType { content_type, body }) => {
+                assert_eq!(content_type, "text/html; charset=utf-8");
+                assert_eq!(body, html);
+            }
+            other => panic!("expected an unexpected content type, got {:?}", other),
+        }
+    }
 
-    let finish_message = match download_name {
-        Some(name) => format!("Downloaded {}", name),
-        None => "Downloaded".into(),
-    };
-    pb.finish_with_message(finish_message);
+    #[test]
+    fn xml_content_types_are_accepted_for_xml() {
+        let xml = "<MediaContainer size=\"0\"/>";
+        for content_type in [
+            Some("text/xml;charset=utf-8"),
+            Some("application/xml"),
+            None,
+        ] {
+            let response = response("200 OK", content_type, xml);
+            assert!(check_content_type(response, "application/xml").is_ok());
+        }
+    }
 
-    return Ok(());
-}
\ No newline at end of file
+    /// Options for a download from the local server, which answers only once
+    fn single_attempt_options() -> DownloadOptions {
+        DownloadOptions {
+            retry_policy: RetryPolicy::never(),
+            compute_checksum: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn downloads_into_memory() {
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world".into());
+        let mut data = vec![];
+        let file = download_to_writer(
+            &mut data,
+            &url,
+            Some("greeting"),
+            Some(11),
+            &NoopSink,
+            &single_attempt_options(),
+        )
+        .unwrap();
+
+        assert_eq!(data, b"hello world");
+        assert_eq!(file.size, 11);
+        assert_eq!(
+            file.sha256.as_deref(),
+            Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+        );
+    }
+
+    #[test]
+    fn short_body_is_a_size_mismatch() {
+        let url = serve("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nhello world".into());
+        let mut data = vec![];
+        let result = download_to_writer(
+            &mut data,
+            &url,
+            None,
+            None,
+            &NoopSink,
+            &single_attempt_options(),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::SizeMismatch {
+                expected: 100,
+                actual: 11
+            })
+        ));
+    }
+
+    /// Downloads the answer `response` of the local server into `folder`, with the events
+    /// reported during the download
+    fn download_into(
+        folder: &Path,
+        response: &str,
+        expected_size: Option<u64>,
+    ) -> (Result<DownloadedFile, Error>, Vec<ProgressEvent>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = download_with_sink(
+            folder.join("song.flac"),
+            &serve(response.into()),
+            Some("song"),
+            expected_size,
+            &ChannelSink::new(sender),
+            &single_attempt_options(),
+        );
+        (result, receiver.try_iter().collect())
+    }
+
+    fn downloaded_bytes(events: &[ProgressEvent]) -> u64 {
+        let bytes = events.iter().map(|event| match event {
+            ProgressEvent::Bytes(count) => *count,
+            _ => 0,
+        });
+        bytes.sum()
+    }
+
+    #[test]
+    fn body_without_content_length_is_downloaded_with_a_spinner() {
+        let folder = TestDir::new("no-length");
+        let response = "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello world";
+        let (result, events) = download_into(&folder, response, None);
+
+        assert_eq!(result.unwrap().size, 11);
+        assert_eq!(fs::read(folder.join("song.flac")).unwrap(), b"hello world");
+        assert_eq!(
+            events.first(),
+            Some(&ProgressEvent::Started {
+                name: "song".into(),
+                total_size: None,
+                resumed_from: 0
+            })
+        );
+        assert_eq!(downloaded_bytes(&events), 11);
+        assert_eq!(events.last(), Some(&ProgressEvent::Finished(11)));
+    }
+
+    #[test]
+    fn chunked_body_shows_the_expected_size() {
+        let folder = TestDir::new("chunked");
+        let response = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+            5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let (result, events) = download_into(&folder, response, Some(11));
+
+        assert_eq!(result.unwrap().size, 11);
+        assert!(matches!(
+            events.first(),
+            Some(ProgressEvent::Started {
+                total_size: Some(11),
+                ..
+            })
+        ));
+        assert_eq!(downloaded_bytes(&events), 11);
+    }
+
+    #[test]
+    fn body_without_content_length_must_have_the_expected_size() {
+        let folder = TestDir::new("no-length-short");
+        let response = "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello world";
+        let (result, events) = download_into(&folder, response, Some(20));
+
+        assert!(matches!(
+            result,
+            Err(Error::UnexpectedSize {
+                expected: 20,
+                actual: 11
+            })
+        ));
+        assert!(!folder.join("song.flac").exists());
+        assert!(matches!(events.last(), Some(ProgressEvent::Failed(_))));
+    }
+
+    /// A body that cancels the download once its first chunk was read
+    struct CancellingReader {
+        chunk: Option<&'static [u8]>,
+        cancellation: CancellationToken,
+    }
+
+    impl Read for CancellingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.cancellation.cancel();
+            let chunk = self.chunk.take().unwrap_or_default();
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn cancelled_copy_keeps_what_was_written() {
+        let folder = TestDir::new("cancelled-copy");
+        let part_path = part_path(&folder.join("song.flac"));
+        let cancellation = CancellationToken::new();
+        let mut reader = CancellingReader {
+            chunk: Some(b"hello"),
+            cancellation: cancellation.clone(),
+        };
+        let mut file = File::create(&part_path).unwrap();
+        let result = copy_with_progress(
+            &mut reader,
+            &mut file,
+            &NoopSink,
+            None,
+            None,
+            &cancellation,
+            false,
+        );
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(fs::read(&part_path).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn cancelled_download_leaves_the_part_file() {
+        let folder = TestDir::new("cancelled-download");
+        let path = folder.join("song.flac");
+        fs::write(part_path(&path), "hello").unwrap();
+        let options = single_attempt_options();
+        options.cancellation.cancel();
+        let result = download_with_sink(
+            path.clone(),
+            "http://127.0.0.1:9/",
+            None,
+            None,
+            &NoopSink,
+            &options,
+        );
+
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(fs::read(part_path(&path)).unwrap(), b"hello");
+        assert!(!path.exists());
+    }
+
+    /// A file on a full disk
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("No space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A body whose connection is reset
+    struct ResetBody;
+
+    impl Read for ResetBody {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("connection reset"))
+        }
+    }
+
+    #[test]
+    fn only_errors_reading_the_body_are_retried() {
+        let copy = |mut reader: &mut dyn Read, mut writer: &mut dyn Write| {
+            let cancellation = CancellationToken::new();
+            let (reader, writer) = (&mut reader, &mut writer);
+            copy_with_progress(reader, writer, &NoopSink, None, None, &cancellation, true)
+        };
+        let policy = RetryPolicy::default();
+
+        let write_error = copy(&mut &b"hello"[..], &mut FullDisk).unwrap_err();
+        assert!(matches!(write_error, Error::IoError(_)));
+        assert!(!policy.is_retryable(&write_error));
+
+        let read_error = copy(&mut ResetBody, &mut vec![]).unwrap_err();
+        assert!(matches!(read_error, Error::BodyReadFailed(_)));
+        assert!(policy.is_retryable(&read_error));
+    }
+
+    #[test]
+    fn size_matching_both_sizes_is_fine() {
+        assert!(check_size(100, Some(100), Some(100)).is_ok());
+        assert!(check_size(100, None, None).is_ok());
+    }
+
+    #[test]
+    fn size_differing_from_content_length_is_a_truncated_transfer() {
+        assert!(matches!(
+            check_size(60, Some(100), Some(100)),
+            Err(Error::SizeMismatch {
+                expected: 100,
+                actual: 60
+            })
+        ));
+    }
+
+    #[test]
+    fn size_differing_from_expected_size_is_unexpected() {
+        assert!(matches!(
+            check_size(120, Some(120), Some(100)),
+            Err(Error::UnexpectedSize {
+                expected: 100,
+                actual: 120
+            })
+        ));
+        assert!(matches!(
+            check_size(120, None, Some(100)),
+            Err(Error::UnexpectedSize { .. })
+        ));
+    }
+}
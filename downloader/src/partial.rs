@@ -0,0 +1,152 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reqwest::blocking::Response;
+use reqwest::header::{ETAG, LAST_MODIFIED};
+
+/// What a `.part` file is downloaded from. It is stored next to the part file, so that
+/// a partial file is only resumed by a download of the same source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartInfo {
+    /// The URL without its query, which may contain a token that changes
+    pub source: String,
+    pub expected_size: Option<u64>,
+    /// Strong `ETag` or `Last-Modified` of the response, sent as `If-Range` when resuming
+    pub validator: Option<String>,
+}
+
+/// Path of the info file of the part file `part_path`, hidden like other bookkeeping files
+pub fn info_path(part_path: &Path) -> PathBuf {
+    let file_name = part_path.file_name().unwrap_or_default().to_string_lossy();
+    part_path.with_file_name(format!(".{}-info", file_name))
+}
+
+impl PartInfo {
+    pub fn new(url: &str, expected_size: Option<u64>) -> Self {
+        PartInfo {
+            source: url.split('?').next().unwrap_or(url).into(),
+            expected_size,
+            validator: None,
+        }
+    }
+
+    /// Whether a part file described by `self` can be continued by a download of `other`
+    pub fn is_same_source(&self, other: &PartInfo) -> bool {
+        self.source == other.source && self.expected_size == other.expected_size
+    }
+
+    /// Takes the validator of the response. Weak ETags can't be used for `If-Range`.
+    pub fn with_validator_of(self, response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let etag = header(ETAG).filter(|etag| !etag.starts_with("W/"));
+        PartInfo {
+            validator: etag.or_else(|| header(LAST_MODIFIED)),
+            ..self
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("source={}\n", self.source);
+        if let Some(size) = self.expected_size {
+            text.push_str(&format!("expected_size={}\n", size));
+        }
+        if let Some(validator) = &self.validator {
+            text.push_str(&format!("validator={}\n", validator));
+        }
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let mut info = PartInfo::new("", None);
+        let mut has_source = false;
+        for line in text.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "source" => {
+                    info.source = value.into();
+                    has_source = true;
+                }
+                "expected_size" => info.expected_size = Some(value.parse().ok()?),
+                "validator" => info.validator = Some(value.into()),
+                _ => return None,
+            }
+        }
+        has_source.then_some(info)
+    }
+
+    /// Reads the info of `part_path`, missing or unreadable info is `None`
+    pub fn read(part_path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(info_path(part_path)).ok()?;
+        Self::from_text(&text)
+    }
+
+    pub fn write(&self, part_path: &Path) -> io::Result<()> {
+        fs::write(info_path(part_path), self.to_text())
+    }
+}
+
+/// Removes the part file and its info, if they exist
+pub fn discard(part_path: &Path) -> io::Result<()> {
+    for path in [part_path.to_path_buf(), info_path(part_path)] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_ignores_the_query() {
+        let info = PartInfo::new(
+            "http://plex/library/parts/1/2/file.flac?X-Plex-Token=a",
+            None,
+        );
+        assert_eq!(info.source, "http://plex/library/parts/1/2/file.flac");
+        let other = PartInfo::new(
+            "http://plex/library/parts/1/2/file.flac?X-Plex-Token=b",
+            None,
+        );
+        assert!(info.is_same_source(&other));
+    }
+
+    #[test]
+    fn other_part_or_size_is_another_source() {
+        let info = PartInfo::new("http://plex/library/parts/1/2/file.flac", Some(100));
+        let replaced = PartInfo::new("http://plex/library/parts/1/3/file.flac", Some(100));
+        let resized = PartInfo::new("http://plex/library/parts/1/2/file.flac", Some(120));
+        assert!(!info.is_same_source(&replaced));
+        assert!(!info.is_same_source(&resized));
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let info = PartInfo {
+            validator: Some("\"abc=\"".into()),
+            ..PartInfo::new("http://plex/file.flac", Some(42))
+        };
+        assert_eq!(PartInfo::from_text(&info.to_text()), Some(info));
+
+        let minimal = PartInfo::new("http://plex/file.flac", None);
+        assert_eq!(PartInfo::from_text(&minimal.to_text()), Some(minimal));
+        assert_eq!(PartInfo::from_text("garbage"), None);
+        assert_eq!(PartInfo::from_text(""), None);
+    }
+
+    #[test]
+    fn info_file_is_hidden() {
+        let path = info_path(Path::new("/music/Artist - Title.flac.part"));
+        assert_eq!(path, Path::new("/music/.Artist - Title.flac.part-info"));
+    }
+}
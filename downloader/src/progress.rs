@@ -0,0 +1,204 @@
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::Error;
+
+/// Receives the events of a download while it is transferred
+pub trait ProgressSink {
+    /// The server answered, `total_size` is known unless the response is chunked.
+    /// `resumed_from` is the number of bytes that were already downloaded before.
+    fn started(&self, name: &str, total_size: Option<u64>, resumed_from: u64);
+    /// `count` more bytes were written
+    fn bytes(&self, count: u64);
+    fn finished(&self, size: u64);
+    fn failed(&self, error: &Error);
+    /// An attempt failed and the download is tried again after `delay`
+    fn retrying(&self, _attempt: u32, _error: &Error, _delay: Duration) {}
+}
+
+/// Ignores all events
+pub struct NoopSink;
+
+impl ProgressSink for NoopSink {
+    fn started(&self, _name: &str, _total_size: Option<u64>, _resumed_from: u64) {}
+    fn bytes(&self, _count: u64) {}
+    fn finished(&self, _size: u64) {}
+    fn failed(&self, _error: &Error) {}
+}
+
+/// Draws the download on an indicatif progress bar
+pub struct IndicatifSink {
+    pb: ProgressBar,
+    name: Mutex<String>,
+}
+
+impl IndicatifSink {
+    pub fn new(pb: ProgressBar) -> Self {
+        Self {
+            pb,
+            name: Mutex::new(String::new()),
+        }
+    }
+}
+
+impl ProgressSink for IndicatifSink {
+    fn started(&self, name: &str, total_size: Option<u64>, resumed_from: u64) {
+        *self.name.lock().unwrap() = name.into();
+
+        match total_size {
+            Some(total_size) => {
+                self.pb.set_length(total_size);
+                self.pb.set_style(ProgressStyle::default_bar()
+                    .template("{msg} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+                    .progress_chars("#>-"));
+            }
+            None => {
+                self.pb.set_style(ProgressStyle::default_spinner().template(
+                    "{msg} {spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})",
+                ));
+            }
+        }
+        self.pb.set_position(resumed_from);
+        self.pb.set_message(format!("Downloading {}", name));
+    }
+
+    fn bytes(&self, count: u64) {
+        self.pb.inc(count);
+    }
+
+    fn finished(&self, _size: u64) {
+        let name = self.name.lock().unwrap();
+        self.pb.finish_with_message(format!("Downloaded {}", name));
+    }
+
+    fn failed(&self, error: &Error) {
+        let name = self.name.lock().unwrap();
+        match error {
+            Error::Cancelled => self.pb.abandon_with_message(format!("Cancelled {}", name)),
+            error => self
+                .pb
+                .abandon_with_message(format!("Failed {}: {:?}", name, error)),
+        }
+    }
+
+    fn retrying(&self, attempt: u32, error: &Error, delay: Duration) {
+        let name = self.name.lock().unwrap();
+        self.pb.set_message(format!(
+            "Retrying {} in {:.1}s (attempt {} failed: {:?})",
+            name,
+            delay.as_secs_f32(),
+            attempt,
+            error
+        ));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    Started {
+        name: String,
+        total_size: Option<u64>,
+        resumed_from: u64,
+    },
+    Bytes(u64),
+    Finished(u64),
+    Failed(String),
+    Retrying {
+        attempt: u32,
+        delay: Duration,
+    },
+}
+
+/// Forwards all events to a channel, e.g. to watch downloads from another thread.
+/// Events are dropped once the receiver is gone.
+pub struct ChannelSink {
+    sender: Mutex<Sender<ProgressEvent>>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: Sender<ProgressEvent>) -> Self {
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    fn send(&self, event: ProgressEvent) {
+        let _ = self.sender.lock().unwrap().send(event);
+    }
+}
+
+impl ProgressSink for ChannelSink {
+    fn started(&self, name: &str, total_size: Option<u64>, resumed_from: u64) {
+        self.send(ProgressEvent::Started {
+            name: name.into(),
+            total_size,
+            resumed_from,
+        });
+    }
+
+    fn bytes(&self, count: u64) {
+        self.send(ProgressEvent::Bytes(count));
+    }
+
+    fn finished(&self, size: u64) {
+        self.send(ProgressEvent::Finished(size));
+    }
+
+    fn failed(&self, error: &Error) {
+        self.send(ProgressEvent::Failed(format!("{:?}", error)));
+    }
+
+    fn retrying(&self, attempt: u32, _error: &Error, delay: Duration) {
+        self.send(ProgressEvent::Retrying { attempt, delay });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::test_support::serve_once;
+    use crate::{download_to_writer, DownloadOptions, RetryPolicy};
+
+    #[test]
+    fn download_is_reported_to_the_channel() {
+        let (address, _) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world");
+        let (sender, receiver) = mpsc::channel();
+        let options = DownloadOptions {
+            retry_policy: RetryPolicy::never(),
+            ..Default::default()
+        };
+        let url = format!("http://{}/", address);
+        download_to_writer(
+            &mut vec![],
+            &url,
+            Some("greeting"),
+            None,
+            &ChannelSink::new(sender),
+            &options,
+        )
+        .unwrap();
+
+        let events = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            events.first(),
+            Some(&ProgressEvent::Started {
+                name: "greeting".into(),
+                total_size: Some(11),
+                resumed_from: 0
+            })
+        );
+        let (last, bytes) = events[1..].split_last().unwrap();
+        assert!(bytes.iter().all(|e| matches!(e, ProgressEvent::Bytes(_))));
+        let downloaded = bytes.iter().map(|e| match e {
+            ProgressEvent::Bytes(count) => *count,
+            _ => 0,
+        });
+        assert_eq!(downloaded.sum::<u64>(), 11);
+        assert_eq!(last, &ProgressEvent::Finished(11));
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Timelike;
+use serde::Deserialize;
+
+/// A time of day range with its own limit, e.g. `from = "18:00"`, `to = "23:30"`.
+/// Ranges may wrap around midnight.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct RateLimitWindow {
+    #[serde(deserialize_with = "time_of_day")]
+    pub from: String,
+    #[serde(deserialize_with = "time_of_day")]
+    pub to: String,
+    /// Limit in bytes per second while the window is active, unlimited if empty
+    #[serde(default)]
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl RateLimitWindow {
+    fn contains(&self, minute_of_day: u32) -> bool {
+        let (from, to) = match (parse_time(&self.from), parse_time(&self.to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return false,
+        };
+        if from <= to {
+            from <= minute_of_day && minute_of_day < to
+        } else {
+            from <= minute_of_day || minute_of_day < to
+        }
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then(|| hours * 60 + minutes)
+}
+
+/// Deserializes an `HH:MM` time, so that invalid windows are rejected when loading them
+fn time_of_day<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+    match parse_time(&time) {
+        Some(_) => Ok(time),
+        None => Err(serde::de::Error::custom(format!(
+            "invalid time of day {:?}, expected HH:MM",
+            time
+        ))),
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket limiting the combined throughput of all downloads sharing it
+#[derive(Debug)]
+pub struct RateLimiter {
+    default_limit: Option<u64>,
+    schedule: Vec<RateLimitWindow>,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// `default_limit` applies outside of the windows of `schedule`, the first matching window
+    /// wins. Times are local time.
+    pub fn new(default_limit: Option<u64>, schedule: Vec<RateLimitWindow>) -> Self {
+        Self {
+            default_limit,
+            schedule,
+            bucket: Mutex::new(Bucket {
+                available: 0.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// The limit in bytes per second that applies right now, `None` if unlimited
+    pub fn current_limit(&self) -> Option<u64> {
+        let now = chrono::Local::now();
+        let minute_of_day = now.hour() * 60 + now.minute();
+        match self.schedule.iter().find(|w| w.contains(minute_of_day)) {
+            Some(window) => window.bytes_per_sec,
+            None => self.default_limit,
+        }
+    }
+
+    /// Accounts for `bytes` that were transferred and blocks as long as needed
+    /// to stay below the current limit
+    pub fn acquire(&self, bytes: u64) {
+        let limit = match self.current_limit() {
+            Some(limit) if limit > 0 => limit as f64,
+            _ => return,
+        };
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            // At most one second worth of bytes can be saved up for bursts
+            bucket.available = (bucket.available + elapsed * limit).min(limit);
+            bucket.last_refill = now;
+            bucket.available -= bytes as f64;
+
+            if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / limit)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::value::{Error, StrDeserializer};
+
+    use super::*;
+
+    fn window(from: &str, to: &str) -> RateLimitWindow {
+        RateLimitWindow {
+            from: from.into(),
+            to: to.into(),
+            bytes_per_sec: None,
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(parse_time("00:00"), Some(0));
+        assert_eq!(parse_time("7:05"), Some(425));
+        assert_eq!(parse_time(" 23:59 "), Some(1439));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("1200"), None);
+        assert_eq!(parse_time("noon"), None);
+    }
+
+    #[test]
+    fn window_includes_its_start_but_not_its_end() {
+        let evening = window("18:00", "23:30");
+        assert!(!evening.contains(17 * 60 + 59));
+        assert!(evening.contains(18 * 60));
+        assert!(evening.contains(23 * 60 + 29));
+        assert!(!evening.contains(23 * 60 + 30));
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let night = window("22:00", "06:00");
+        assert!(night.contains(22 * 60));
+        assert!(night.contains(0));
+        assert!(night.contains(5 * 60 + 59));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+    }
+
+    #[test]
+    fn invalid_times_are_rejected() {
+        let valid = time_of_day(StrDeserializer::<Error>::new("18:00"));
+        assert_eq!(valid.unwrap(), "18:00");
+        assert!(time_of_day(StrDeserializer::<Error>::new("25:00")).is_err());
+        assert!(time_of_day(StrDeserializer::<Error>::new("6pm")).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::{CancellationToken, Error};
+
+/// How often a cancellation is checked while waiting for the next attempt
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub retryable_status_codes: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            retryable_status_codes: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+/// Called with a failed attempt, its error and the delay before the next attempt
+pub type RetryReporter = fn(u32, &Error, Duration);
+
+/// How requests other than downloads are retried, and where their retries are reported
+#[derive(Debug, Clone, Default)]
+pub struct RequestRetry {
+    pub policy: RetryPolicy,
+    pub reporter: Option<RetryReporter>,
+}
+
+impl RequestRetry {
+    fn report(&self, attempt: u32, error: &Error, delay: Duration) {
+        if let Some(reporter) = self.reporter {
+            reporter(attempt, error, delay);
+        }
+    }
+
+    /// Runs `operation` with the policy, reporting every retry
+    pub fn run<T, O>(&self, operation: O) -> Result<T, Error>
+    where
+        O: FnMut() -> Result<T, Error>,
+    {
+        self.policy.run(
+            |attempt, error, delay| self.report(attempt, error, delay),
+            operation,
+        )
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that gives up after the first failure
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::GetRequestFailed(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+            }
+            // Network errors while streaming the body, often `Other` I/O errors. Errors of
+            // the local file system, like a full disk, fail again and aren't retried.
+            Error::BodyReadFailed(_) => true,
+            // A truncated transfer, the next attempt resumes from the part file
+            Error::SizeMismatch { .. } => true,
+            _ => error
+                .status()
+                .is_some_and(|status| self.retryable_status_codes.contains(&status)),
+        }
+    }
+
+    /// Exponential backoff with full jitter for the given (1-based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_backoff);
+        Duration::from_millis(fastrand::u64(0..=capped.as_millis() as u64))
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that isn't retryable
+    /// or runs out of attempts. `on_retry` is called with the failed attempt, its error
+    /// and the delay before the next attempt.
+    pub fn run<T, R, O>(&self, on_retry: R, operation: O) -> Result<T, Error>
+    where
+        R: FnMut(u32, &Error, Duration),
+        O: FnMut() -> Result<T, Error>,
+    {
+        self.run_cancellable(&CancellationToken::new(), on_retry, operation)
+    }
+
+    /// Like `run`, but stops waiting for the next attempt once `cancellation` is cancelled
+    pub fn run_cancellable<T, R, O>(
+        &self,
+        cancellation: &CancellationToken,
+        mut on_retry: R,
+        mut operation: O,
+    ) -> Result<T, Error>
+    where
+        R: FnMut(u32, &Error, Duration),
+        O: FnMut() -> Result<T, Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(result) => return Ok(result),
+                Err(e) if attempt < self.max_attempts && self.is_retryable(&e) => {
+                    let delay = self.backoff(attempt);
+                    on_retry(attempt, &e, delay);
+                    sleep_unless_cancelled(delay, cancellation)?;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn sleep_unless_cancelled(delay: Duration, cancellation: &CancellationToken) -> Result<(), Error> {
+    let mut remaining = delay;
+    while !remaining.is_zero() {
+        if cancellation.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let step = remaining.min(CANCELLATION_POLL_INTERVAL);
+        thread::sleep(step);
+        remaining -= step;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn backoff_grows_up_to_the_maximum() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            assert!(policy.backoff(1) <= Duration::from_millis(100));
+            assert!(policy.backoff(3) <= Duration::from_millis(400));
+            assert!(policy.backoff(5) <= Duration::from_millis(1000));
+            assert!(policy.backoff(u32::MAX) <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn backoff_is_jittered() {
+        let policy = RetryPolicy::default();
+        let delays = (0..100).map(|_| policy.backoff(4)).collect::<Vec<_>>();
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn network_errors_and_truncated_transfers_are_retryable() {
+        let policy = RetryPolicy::default();
+        for kind in [
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::TimedOut,
+            io::ErrorKind::Other,
+        ] {
+            assert!(policy.is_retryable(&Error::BodyReadFailed(kind.into())));
+        }
+        let truncated = Error::SizeMismatch {
+            expected: 10,
+            actual: 5,
+        };
+        assert!(policy.is_retryable(&truncated));
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::Other] {
+            assert!(!policy.is_retryable(&Error::IoError(kind.into())));
+        }
+    }
+
+    #[test]
+    fn only_configured_statuses_are_retryable() {
+        let body = String::new();
+        let policy = RetryPolicy::default();
+        assert!(policy.is_retryable(&Error::RateLimited { body: body.clone() }));
+        let unavailable = Error::ServerError {
+            status: 503,
+            body: body.clone(),
+        };
+        assert!(policy.is_retryable(&unavailable));
+        assert!(!policy.is_retryable(&Error::NotFound { body: body.clone() }));
+        assert!(!policy.is_retryable(&Error::Unauthorized { body: body.clone() }));
+        assert!(!policy.is_retryable(&Error::Cancelled));
+
+        let policy = RetryPolicy {
+            retryable_status_codes: vec![],
+            ..Default::default()
+        };
+        assert!(!policy.is_retryable(&unavailable));
+    }
+
+    #[test]
+    fn request_retry_reports_retries() {
+        let retry = RequestRetry {
+            policy: RetryPolicy {
+                max_attempts: 3,
+                initial_backoff: Duration::ZERO,
+                ..Default::default()
+            },
+            reporter: Some(|attempt, error, _| {
+                assert!(attempt < 3);
+                assert!(matches!(error, Error::RateLimited { .. }));
+            }),
+        };
+        let mut attempts = 0;
+        let result: Result<(), Error> = retry.run(|| {
+            attempts += 1;
+            Err(Error::RateLimited {
+                body: String::new(),
+            })
+        });
+        assert!(matches!(result, Err(Error::RateLimited { .. })));
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,78 @@
+//! Helpers for the tests of this crate and the crates using it
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+/// An empty folder in the temp directory for a test. It is removed when it is dropped,
+/// also if the test fails.
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// A folder for the test `name`, unique among the tests of the process
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("chiubi-cloud-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A server on localhost that answers the first request with `response`. Returns its
+/// address and a handle yielding the head of the request it received.
+pub fn serve_once(response: impl Into<Vec<u8>>) -> (SocketAddr, JoinHandle<String>) {
+    let response = response.into();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || answer(&listener, &response));
+    (address, handle)
+}
+
+/// A server on localhost that answers a request on each new connection with the next of
+/// `responses`, which should close the connection. Returns its address and a handle
+/// yielding the heads of the requests it received.
+pub fn serve_each(responses: Vec<String>) -> (SocketAddr, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let answer = |response: String| answer(&listener, response.as_bytes());
+        responses.into_iter().map(answer).collect()
+    });
+    (address, handle)
+}
+
+/// Accepts a connection, reads the head of its request and writes `response`
+fn answer(listener: &TcpListener, response: &[u8]) -> String {
+    let (mut stream, _) = listener.accept().unwrap();
+    // Closing the stream with unread data resets it, so the whole request is read
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buffer).unwrap() {
+            0 => break,
+            length => request.extend_from_slice(&buffer[..length]),
+        }
+    }
+    stream.write_all(response).unwrap();
+    String::from_utf8_lossy(&request).into_owned()
+}
@@ -1,14 +1,34 @@
 use std::fmt::{Display, Formatter};
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+use downloader::{HttpConfig, RequestRetry};
+
+use crate::format::ResponseFormat;
+
+const PLEX_TV_URL: &str = "https://plex.tv";
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
 pub struct PlexConfig {
     pub token: String,
     pub url: String,
+    /// Token of the Plex account, set when `token` was switched to a Plex Home user
+    #[serde(default)]
+    pub account_token: Option<String>,
+    /// Title of the Plex Home user the token belongs to
+    #[serde(default)]
+    pub home_user: Option<String>,
+    #[serde(default)]
+    pub format: ResponseFormat,
+    /// Used for the configured server, plex.tv requests always verify certificates
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Retries of failed requests, set by the application instead of being stored
+    #[serde(skip)]
+    pub retry: RequestRetry,
 }
 
 impl Display for PlexConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format!("{:?}", self))
+        write!(f, "{:?}", self)
     }
 }
 
@@ -17,16 +37,109 @@ impl PlexConfig {
         format!("{}/playlists?playlistType=audio&includeCollections=1&includeExternalMedia=1&includeAdvanced=1&includeMeta=1&{}", self.url, self.get_static_query_params())
     }
 
+    pub fn get_identity_url(&self) -> String {
+        format!("{}/identity", self.url)
+    }
+
+    pub fn get_root_url(&self) -> String {
+        format!("{}/?{}", self.url, self.get_static_query_params())
+    }
+
+    pub fn get_notifications_url(&self) -> String {
+        let ws_url = if let Some(host) = self.url.strip_prefix("https://") {
+            format!("wss://{}", host)
+        } else if let Some(host) = self.url.strip_prefix("http://") {
+            format!("ws://{}", host)
+        } else {
+            self.url.clone()
+        };
+        format!(
+            "{}/:/websockets/notifications?{}",
+            ws_url,
+            self.get_static_query_params()
+        )
+    }
+
+    pub fn get_home_users_url(&self) -> String {
+        format!(
+            "{}/api/home/users?{}",
+            PLEX_TV_URL,
+            self.get_account_query_params()
+        )
+    }
+
+    pub fn get_switch_home_user_url(&self, user_id: &str, pin: Option<&str>) -> String {
+        let pin = pin.map(|p| format!("pin={}&", p)).unwrap_or_default();
+        format!(
+            "{}/api/home/users/{}/switch?{}{}",
+            PLEX_TV_URL,
+            user_id,
+            pin,
+            self.get_account_query_params()
+        )
+    }
+
+    pub fn get_resources_url(user_token: &str) -> String {
+        format!(
+            "{}/api/resources?includeHttps=1&{}",
+            PLEX_TV_URL,
+            query_params_for_token(user_token)
+        )
+    }
+
+    /// HTTP settings for plex.tv requests, which carry the account token. Invalid
+    /// certificates are only accepted from the configured server.
+    pub fn get_account_http(&self) -> HttpConfig {
+        HttpConfig {
+            accept_invalid_certs: false,
+            ..self.http.clone()
+        }
+    }
+
+    /// The token used for plex.tv requests, which stays the same when switching Home users
+    pub fn get_account_token(&self) -> &str {
+        self.account_token.as_deref().unwrap_or(&self.token)
+    }
+
+    pub fn get_account_query_params(&self) -> String {
+        query_params_for_token(self.get_account_token())
+    }
+
     pub fn get_static_query_params(&self) -> String {
-        format!("X-Plex-Token={}&X-Plex-Product=Plex%20Web&X-Plex-Version=4.64.3&X-Plex-Client-Identifier=mg7p5uivc6f90wsoxu2asvad&X-Plex-Platform=Chrome&X-Plex-Platform-Version=92.0&X-Plex-Sync-Version=2&X-Plex-Features=external-media%2Cindirect-media&X-Plex-Model=hosted&X-Plex-Device=Windows&X-Plex-Device-Name=Chrome&X-Plex-Device-Screen-Resolution=2498x632%2C2560x1440&X-Plex-Language=en-GB&X-Plex-Drm=widevine&X-Plex-Text-Format=plain&X-Plex-Provider-Version=3.2", self.token)
+        query_params_for_token(&self.token)
+    }
+
+    /// The URL of the file of a media part on the server, with the current token
+    pub fn get_download_url(&self, part_key: &str) -> String {
+        format!(
+            "{}{}?{}",
+            self.url,
+            part_key,
+            self.get_static_query_params()
+        )
     }
 }
 
-impl Default for PlexConfig {
-    fn default() -> Self {
-        Self {
-            token: Default::default(),
-            url: Default::default(),
-        }
+fn query_params_for_token(token: &str) -> String {
+    format!("X-Plex-Token={}&X-Plex-Product=Plex%20Web&X-Plex-Version=4.64.3&X-Plex-Client-Identifier=mg7p5uivc6f90wsoxu2asvad&X-Plex-Platform=Chrome&X-Plex-Platform-Version=92.0&X-Plex-Sync-Version=2&X-Plex-Features=external-media%2Cindirect-media&X-Plex-Model=hosted&X-Plex-Device=Windows&X-Plex-Device-Name=Chrome&X-Plex-Device-Screen-Resolution=2498x632%2C2560x1440&X-Plex-Language=en-GB&X-Plex-Drm=widevine&X-Plex-Text-Format=plain&X-Plex-Provider-Version=3.2", token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_requests_verify_certificates() {
+        let config = PlexConfig {
+            http: HttpConfig {
+                accept_invalid_certs: true,
+                proxy: Some("http://proxy:3128".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let http = config.get_account_http();
+        assert!(!http.accept_invalid_certs);
+        assert_eq!(http.proxy, config.http.proxy);
     }
 }
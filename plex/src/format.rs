@@ -0,0 +1,126 @@
+use serde::de::DeserializeOwned;
+
+/// Representation requested from the Plex server
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Xml,
+    Json,
+}
+
+impl ResponseFormat {
+    pub fn accept_header(&self) -> &'static str {
+        match self {
+            ResponseFormat::Xml => "application/xml",
+            ResponseFormat::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Xml(quick_xml::DeError),
+    Json(serde_json::Error),
+}
+
+// The XML root element is the container itself, in JSON it is wrapped in an object
+#[derive(serde::Deserialize)]
+struct JsonContainer<T> {
+    #[serde(rename = "MediaContainer")]
+    media_container: T,
+}
+
+/// Deserializes a `MediaContainer` response in the given representation
+pub fn parse<T: DeserializeOwned>(format: ResponseFormat, body: &str) -> Result<T, ParseError> {
+    match format {
+        ResponseFormat::Xml => quick_xml::de::from_str(body).map_err(ParseError::Xml),
+        ResponseFormat::Json => serde_json::from_str::<JsonContainer<T>>(body)
+            .map(|c| c.media_container)
+            .map_err(ParseError::Json),
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(u64),
+}
+
+/// Deserializes an id that is a string in XML and may be a number in JSON
+pub(crate) fn id_as_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let id: Option<StringOrNumber> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(id.map(|id| match id {
+        StringOrNumber::String(id) => id,
+        StringOrNumber::Number(id) => id.to_string(),
+    }))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::{Playlist, PlaylistsContainer};
+
+    /// Parses the fixture `<name>.xml` or `<name>.json` from `tests/fixtures`
+    pub(crate) fn parse_fixture<T: DeserializeOwned>(name: &str, format: ResponseFormat) -> T {
+        let extension = match format {
+            ResponseFormat::Xml => "xml",
+            ResponseFormat::Json => "json",
+        };
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("{}.{}", name, extension));
+        let body = fs::read_to_string(&path).unwrap();
+        parse(format, &body).unwrap()
+    }
+
+    #[test]
+    fn parses_playlists() {
+        let xml: PlaylistsContainer = parse_fixture("playlists", ResponseFormat::Xml);
+        let json: PlaylistsContainer = parse_fixture("playlists", ResponseFormat::Json);
+        assert_eq!(xml, json);
+
+        let playlists = xml.playlists;
+        assert_eq!(playlists.len(), 2);
+        assert_eq!(playlists[0].key(), "/playlists/101/items");
+        assert_eq!(playlists[0].rating_key(), Some("101"));
+        assert_eq!(playlists[0].title, "Road Trip");
+        assert_eq!(playlists[0].summary, "Songs for the car");
+        assert_eq!(playlists[1].title, "Focus & Flow");
+        assert_eq!(playlists[1].summary, "");
+    }
+
+    #[test]
+    fn parses_playlist_details() {
+        let xml: Playlist = parse_fixture("playlist", ResponseFormat::Xml);
+        let json: Playlist = parse_fixture("playlist", ResponseFormat::Json);
+        assert_eq!(xml, json);
+
+        assert_eq!(xml.title, "Road Trip");
+        assert_eq!(xml.tracks.len(), 2);
+        let track = &xml.tracks[1];
+        assert_eq!(track.rating_key(), Some("2002"));
+        assert_eq!(track.title, "Second & Last");
+        assert_eq!(track.artist, "Other Artist");
+        assert_eq!(track.album, "Other Album");
+        assert_eq!(track.duration(), Some(212000));
+        assert_eq!(track.get_part_id(), Some("4002"));
+        assert_eq!(track.get_file_size(), Some(8480000));
+        assert_eq!(track.get_file_extension(), ".mp3");
+    }
+
+    #[test]
+    fn parse_error_names_the_format() {
+        let xml = parse::<PlaylistsContainer>(ResponseFormat::Xml, "{}");
+        assert!(matches!(xml, Err(ParseError::Xml(_))));
+        let json = parse::<PlaylistsContainer>(ResponseFormat::Json, "<MediaContainer/>");
+        assert!(matches!(json, Err(ParseError::Json(_))));
+    }
+}
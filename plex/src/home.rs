@@ -0,0 +1,170 @@
+use downloader::{get_xml_from_url, post_to_url};
+
+use crate::config::PlexConfig;
+use crate::format::{parse, ParseError, ResponseFormat};
+
+#[derive(Debug)]
+pub enum Error {
+    RequestFailed(downloader::Error),
+    InvalidResponse(ParseError),
+    Unauthorized,
+    ServerNotShared,
+    ServerError(crate::Error),
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq, Clone)]
+#[serde(rename = "User")]
+pub struct HomeUser {
+    pub id: String,
+    pub uuid: String,
+    pub title: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub restricted: bool,
+    /// Protected users can only be switched to with their PIN
+    #[serde(default)]
+    pub protected: bool,
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "MediaContainer")]
+struct XmlHomeUsers {
+    #[serde(rename = "User", default)]
+    users: Vec<HomeUser>,
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "user")]
+struct XmlSwitchedUser {
+    #[serde(rename = "authenticationToken")]
+    authentication_token: String,
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "Device")]
+struct XmlDevice {
+    #[serde(rename = "clientIdentifier")]
+    client_identifier: String,
+    #[serde(rename = "accessToken", default)]
+    access_token: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "MediaContainer")]
+struct XmlResources {
+    #[serde(rename = "Device", default)]
+    devices: Vec<XmlDevice>,
+}
+
+fn request_error(error: downloader::Error) -> Error {
+    match error {
+        downloader::Error::Unauthorized { .. } => Error::Unauthorized,
+        error => Error::RequestFailed(error),
+    }
+}
+
+/// The access token of the server with `machine_identifier` among the resources of a user
+fn server_access_token(resources: XmlResources, machine_identifier: &str) -> Result<String, Error> {
+    resources
+        .devices
+        .into_iter()
+        .find(|d| d.client_identifier == machine_identifier)
+        .and_then(|d| d.access_token)
+        .ok_or(Error::ServerNotShared)
+}
+
+// The plex.tv endpoints used here only answer in XML, regardless of the configured format
+
+/// Lists the users of the Plex Home the configured account belongs to
+pub fn fetch_users(config: &PlexConfig) -> Result<Vec<HomeUser>, Error> {
+    let http = config.get_account_http();
+    let xml_str = get_xml_from_url(config.get_home_users_url(), &http, &config.retry)
+        .map_err(request_error)?;
+    let xml_users: XmlHomeUsers =
+        parse(ResponseFormat::Xml, &xml_str).map_err(Error::InvalidResponse)?;
+    Ok(xml_users.users)
+}
+
+/// Switches to a Plex Home user and returns a configuration with a token scoped to that user
+/// on the configured server. `pin` is required for protected users.
+pub fn switch_user(
+    config: &PlexConfig,
+    user: &HomeUser,
+    pin: Option<&str>,
+) -> Result<PlexConfig, Error> {
+    let http = config.get_account_http();
+    let switch_url = config.get_switch_home_user_url(&user.id, pin);
+    let xml_str = post_to_url(switch_url, ResponseFormat::Xml.accept_header(), &http)
+        .map_err(request_error)?;
+    let switched: XmlSwitchedUser =
+        parse(ResponseFormat::Xml, &xml_str).map_err(Error::InvalidResponse)?;
+
+    // The user token is valid for plex.tv, the server needs its own access token
+    let server = crate::server::fetch_status(config).map_err(Error::ServerError)?;
+    let resources_url = PlexConfig::get_resources_url(&switched.authentication_token);
+    let xml_str = get_xml_from_url(resources_url, &http, &config.retry).map_err(request_error)?;
+    let resources: XmlResources =
+        parse(ResponseFormat::Xml, &xml_str).map_err(Error::InvalidResponse)?;
+
+    let access_token = server_access_token(resources, &server.machine_identifier)?;
+
+    Ok(PlexConfig {
+        token: access_token,
+        account_token: Some(config.get_account_token().into()),
+        home_user: Some(user.title.clone()),
+        ..config.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::tests::parse_fixture;
+
+    const SERVER_ID: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn home_users_are_parsed() {
+        let home: XmlHomeUsers = parse_fixture("home_users", ResponseFormat::Xml);
+
+        assert_eq!(home.users.len(), 2);
+        let admin = &home.users[0];
+        assert_eq!(admin.id, "1001");
+        assert_eq!(admin.title, "Alex");
+        assert_eq!(admin.username.as_deref(), Some("alex"));
+        assert!(admin.admin && !admin.restricted && !admin.protected);
+        let kids = &home.users[1];
+        assert_eq!(kids.title, "Kids");
+        assert!(!kids.admin && kids.restricted && kids.protected);
+    }
+
+    #[test]
+    fn token_of_the_switched_user_is_parsed() {
+        let switched: XmlSwitchedUser = parse_fixture("switch_user", ResponseFormat::Xml);
+        assert_eq!(switched.authentication_token, "kids-user-token");
+    }
+
+    #[test]
+    fn access_token_of_the_server_is_found() {
+        let resources: XmlResources = parse_fixture("resources", ResponseFormat::Xml);
+        assert_eq!(resources.devices.len(), 3);
+        assert_eq!(
+            server_access_token(resources, SERVER_ID).unwrap(),
+            "kids-server-token"
+        );
+    }
+
+    #[test]
+    fn server_without_access_token_is_not_shared() {
+        for machine_identifier in ["89abcdef0123456789abcdef0123456789abcdef", "unknown"] {
+            let resources: XmlResources = parse_fixture("resources", ResponseFormat::Xml);
+            assert!(matches!(
+                server_access_token(resources, machine_identifier),
+                Err(Error::ServerNotShared)
+            ));
+        }
+    }
+}
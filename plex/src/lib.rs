@@ -1,58 +1,107 @@
 pub mod config;
+pub mod format;
+pub mod home;
+pub mod notifications;
+pub mod server;
+mod websocket;
 
 use config::PlexConfig;
-use downloader::get_xml_from_url;
-use quick_xml::de::from_str;
+use downloader::get_from_url;
+use format::{parse, ParseError};
 use sanitize_filename::sanitize;
+use serde::de::DeserializeOwned;
 
-#[derive(Debug, serde::Deserialize, PartialEq)]
+#[derive(Debug)]
+pub enum Error {
+    RequestFailed(downloader::Error),
+    InvalidResponse(ParseError),
+}
+
+/// Requests `url` from the Plex server in the configured format and deserializes the response
+pub(crate) fn fetch<T: DeserializeOwned>(config: &PlexConfig, url: String) -> Result<T, Error> {
+    let body = get_from_url(
+        url,
+        config.format.accept_header(),
+        &config.http,
+        &config.retry,
+    )
+    .map_err(Error::RequestFailed)?;
+    parse(config.format, &body).map_err(Error::InvalidResponse)
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
 #[serde(rename = "Part")]
-struct XmlTrackMediaParts {
+pub struct Part {
+    #[serde(default, deserialize_with = "format::id_as_string")]
+    id: Option<String>,
     key: String,
+    /// Size of the file on the server in bytes
+    #[serde(default)]
+    size: Option<u64>,
 }
 
-#[derive(Debug, serde::Deserialize, PartialEq)]
+impl Part {
+    /// The id of the file on the server, it changes when the file is replaced
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
 #[serde(rename = "Media")]
-struct TrackMedia {
-    #[serde(rename = "Part")]
-    parts: Vec<XmlTrackMediaParts>,
+pub struct Media {
+    #[serde(alias = "Part")]
+    parts: Vec<Part>,
 }
 
-#[derive(Debug, serde::Deserialize, PartialEq)]
+impl Media {
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+}
+
+// Field names are serialized as written, the aliases accept Plex' own attribute names
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
 #[serde(rename = "Track")]
 pub struct Track {
+    #[serde(alias = "ratingKey", default)]
+    rating_key: Option<String>,
     pub title: String,
-    #[serde(rename = "parentTitle")]
+    #[serde(alias = "parentTitle")]
     pub album: String,
-    #[serde(rename = "grandparentTitle")]
+    #[serde(alias = "grandparentTitle")]
     pub artist: String,
+    /// Duration in milliseconds
+    #[serde(default)]
+    duration: Option<u64>,
 
-    #[serde(rename = "Media")]
-    media: Vec<TrackMedia>,
+    #[serde(alias = "Media")]
+    media: Vec<Media>,
 }
 
 impl Track {
     pub fn get_download_url(&self, config: &PlexConfig) -> String {
         assert!(self.media.len() == 1);
         assert!(self.media[0].parts.len() == 1);
-        let parts_key = self.get_parts_key();
-        format!(
-            "{}{}?{}",
-            config.url,
-            parts_key,
-            config.get_static_query_params()
-        )
+        config.get_download_url(&self.get_parts_key())
     }
 
     pub fn get_file_extension(&self) -> String {
         let parts_key = self.get_parts_key();
-        let delimiter = parts_key.find(".").expect(
-            format!(
+        let delimiter = parts_key.find('.').unwrap_or_else(|| {
+            panic!(
                 "Could not detect file extension for parts key {}",
                 parts_key
             )
-            .as_str(),
-        );
+        });
         parts_key[delimiter..].into()
     }
 
@@ -66,29 +115,75 @@ impl Track {
         sanitize(raw_name)
     }
 
-    fn get_parts_key(&self) -> String {
+    pub fn get_file_size(&self) -> Option<u64> {
+        self.media.first()?.parts.first()?.size
+    }
+
+    /// The id of the track on the server, it stays the same when the metadata changes
+    pub fn rating_key(&self) -> Option<&str> {
+        self.rating_key.as_deref()
+    }
+
+    pub fn duration(&self) -> Option<u64> {
+        self.duration
+    }
+
+    pub fn get_part_id(&self) -> Option<&str> {
+        self.media.first()?.parts.first()?.id()
+    }
+
+    pub fn media(&self) -> &[Media] {
+        &self.media
+    }
+
+    /// Opens the audio file of the track for reading, e.g. to proxy it without saving it
+    pub fn open_stream(&self, config: &PlexConfig) -> Result<downloader::DownloadStream, Error> {
+        let options = downloader::DownloadOptions {
+            retry_policy: config.retry.policy.clone(),
+            http: config.http.clone(),
+            ..Default::default()
+        };
+        downloader::open_stream(&self.get_download_url(config), &options)
+            .map_err(Error::RequestFailed)
+    }
+
+    /// The key of the file of the track on the server, without the token of a download URL
+    pub fn get_parts_key(&self) -> String {
         self.media[0].parts[0].key.clone()
     }
 }
 
-#[derive(Debug, serde::Deserialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
 #[serde(rename = "Playlist")]
 pub struct Playlist {
     pub title: String,
 
-    #[serde(rename = "Track")]
+    // Tracks are `Track` elements in XML and `Metadata` entries in JSON
+    #[serde(alias = "Track", alias = "Metadata", default)]
     pub tracks: Vec<Track>,
 }
 
-#[derive(Debug, serde::Deserialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
 #[serde(rename = "Playlist")]
 pub struct PlaylistOverview {
     key: String,
+    #[serde(alias = "ratingKey", default)]
+    rating_key: Option<String>,
     pub title: String,
+    #[serde(default)]
     pub summary: String,
 }
 
 impl PlaylistOverview {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The id of the playlist on the server
+    pub fn rating_key(&self) -> Option<&str> {
+        self.rating_key.as_deref()
+    }
+
     pub fn get_playlist_url(&self, config: &PlexConfig) -> String {
         format!(
             "{}{}?{}",
@@ -98,29 +193,92 @@ impl PlaylistOverview {
         )
     }
 
-    pub fn into_detailed_playlist(&self, config: &PlexConfig) -> Playlist {
-        let url = self.get_playlist_url(config);
-        let xml_str = get_xml_from_url(url).unwrap();
-        let xml_str = xml_str.as_str();
-        let playlist: Playlist = from_str(xml_str).unwrap();
-        playlist
+    pub fn into_detailed_playlist(&self, config: &PlexConfig) -> Result<Playlist, Error> {
+        fetch(config, self.get_playlist_url(config))
     }
 }
 
 #[derive(Debug, serde::Deserialize, PartialEq)]
 #[serde(rename = "MediaContainer")]
-struct XmlPlaylists {
-    #[serde(rename = "Playlist")]
+struct PlaylistsContainer {
+    #[serde(rename = "Playlist", alias = "Metadata", default)]
     pub playlists: Vec<PlaylistOverview>,
 }
 
 pub mod playlists {
     use super::*;
 
-    pub fn fetch_all(config: &PlexConfig) -> Vec<PlaylistOverview> {
-        let xml_str = get_xml_from_url(config.get_playlists_url()).unwrap();
-        let xml_str = xml_str.as_str();
-        let xml_playlists: XmlPlaylists = from_str(xml_str).unwrap();
-        xml_playlists.playlists
+    pub fn fetch_all(config: &PlexConfig) -> Result<Vec<PlaylistOverview>, Error> {
+        let container: PlaylistsContainer = fetch(config, config.get_playlists_url())?;
+        Ok(container.playlists)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACK_JSON: &str = r#"{
+        "ratingKey": "42",
+        "title": "Title",
+        "parentTitle": "Album",
+        "grandparentTitle": "Artist",
+        "duration": 180000,
+        "Media": [{"Part": [{"id": 7, "key": "/library/parts/7/file.flac", "size": 1234}]}]
+    }"#;
+
+    fn track() -> Track {
+        serde_json::from_str(TRACK_JSON).unwrap()
+    }
+
+    #[test]
+    fn track_accepts_plex_attribute_names() {
+        let track = track();
+        assert_eq!(track.rating_key(), Some("42"));
+        assert_eq!(track.title, "Title");
+        assert_eq!(track.album, "Album");
+        assert_eq!(track.artist, "Artist");
+        assert_eq!(track.duration(), Some(180000));
+        assert_eq!(track.get_part_id(), Some("7"));
+        assert_eq!(track.get_file_size(), Some(1234));
+        assert_eq!(
+            track.media()[0].parts()[0].key(),
+            "/library/parts/7/file.flac"
+        );
+        assert_eq!(track.infer_file_name(), "Artist - Title.flac");
+    }
+
+    #[test]
+    fn track_round_trips() {
+        let track = track();
+        let json = serde_json::to_string(&track).unwrap();
+        assert_eq!(serde_json::from_str::<Track>(&json).unwrap(), track);
+    }
+
+    #[test]
+    fn playlist_round_trips() {
+        let playlist = Playlist {
+            title: "Mix".into(),
+            tracks: vec![track(), track()],
+        };
+        let json = serde_json::to_string(&playlist).unwrap();
+        assert_eq!(serde_json::from_str::<Playlist>(&json).unwrap(), playlist);
+    }
+
+    #[test]
+    fn playlist_overview_round_trips() {
+        let overview: PlaylistOverview = serde_json::from_str(
+            r#"{"key": "/playlists/5/items", "ratingKey": "5", "title": "Mix"}"#,
+        )
+        .unwrap();
+        assert_eq!(overview.key(), "/playlists/5/items");
+        assert_eq!(overview.rating_key(), Some("5"));
+        assert_eq!(overview.summary, "");
+
+        let json = serde_json::to_string(&overview).unwrap();
+        assert_eq!(
+            serde_json::from_str::<PlaylistOverview>(&json).unwrap(),
+            overview
+        );
     }
 }
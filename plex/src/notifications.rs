@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use downloader::HttpConfig;
+use serde::Deserialize;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::Message;
+
+use crate::config::PlexConfig;
+use crate::websocket::{self, Socket};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Without a message for this long the server is pinged. If it stays silent for the same
+/// time again, the connection is considered lost.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Metadata type Plex uses for playlists in timeline entries
+const PLAYLIST_METADATA_TYPE: u32 = 15;
+
+#[derive(Debug)]
+pub enum Error {
+    ConnectionFailed(Box<tungstenite::Error>),
+    /// The server rejected the token, reconnecting won't help
+    Unauthorized,
+    /// The proxy isn't supported or a root certificate can't be used, reconnecting won't help
+    InvalidHttpConfig(String),
+    InvalidMessage(serde_json::Error),
+}
+
+impl Error {
+    pub(crate) fn connection_failed(error: tungstenite::Error) -> Self {
+        Error::ConnectionFailed(Box::new(error))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    Timeline(Vec<TimelineEntry>),
+    Playlist(Vec<TimelineEntry>),
+    Activity(Vec<ActivityNotification>),
+    Playing(Vec<PlaySessionStateNotification>),
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "u32")]
+pub enum TimelineState {
+    Created,
+    Processing,
+    Matching,
+    Downloading,
+    Loading,
+    Finished,
+    Analyzing,
+    Deleted,
+    Other(u32),
+}
+
+impl From<u32> for TimelineState {
+    fn from(state: u32) -> Self {
+        match state {
+            0 => TimelineState::Created,
+            1 => TimelineState::Processing,
+            2 => TimelineState::Matching,
+            3 => TimelineState::Downloading,
+            4 => TimelineState::Loading,
+            5 => TimelineState::Finished,
+            6 => TimelineState::Analyzing,
+            9 => TimelineState::Deleted,
+            other => TimelineState::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TimelineEntry {
+    #[serde(rename = "itemID")]
+    pub item_id: String,
+    #[serde(rename = "type")]
+    pub metadata_type: u32,
+    #[serde(default)]
+    pub title: Option<String>,
+    pub state: TimelineState,
+    #[serde(rename = "updatedAt", default)]
+    pub updated_at: Option<u64>,
+}
+
+impl TimelineEntry {
+    pub fn is_playlist(&self) -> bool {
+        self.metadata_type == PLAYLIST_METADATA_TYPE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivityEvent {
+    Started,
+    Updated,
+    Ended,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Activity {
+    #[serde(rename = "type")]
+    pub activity_type: String,
+    pub title: String,
+    #[serde(default)]
+    pub subtitle: Option<String>,
+    #[serde(default)]
+    pub progress: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ActivityNotification {
+    pub event: ActivityEvent,
+    pub uuid: String,
+    #[serde(rename = "Activity")]
+    pub activity: Activity,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PlaySessionStateNotification {
+    #[serde(rename = "sessionKey")]
+    pub session_key: String,
+    #[serde(rename = "ratingKey")]
+    pub rating_key: String,
+    pub state: String,
+    #[serde(rename = "viewOffset", default)]
+    pub view_offset: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NotificationContainer {
+    #[serde(rename = "type")]
+    notification_type: String,
+    #[serde(rename = "TimelineEntry", default)]
+    timeline: Vec<TimelineEntry>,
+    #[serde(rename = "ActivityNotification", default)]
+    activities: Vec<ActivityNotification>,
+    #[serde(rename = "PlaySessionStateNotification", default)]
+    play_sessions: Vec<PlaySessionStateNotification>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NotificationMessage {
+    #[serde(rename = "NotificationContainer")]
+    container: NotificationContainer,
+}
+
+/// Decodes a single websocket message into the notifications it contains.
+/// Playlist changes are reported separately from other timeline entries.
+pub fn decode(message: &str) -> Result<Vec<Notification>, Error> {
+    let message: NotificationMessage =
+        serde_json::from_str(message).map_err(Error::InvalidMessage)?;
+    let container = message.container;
+
+    let notifications = match container.notification_type.as_str() {
+        "timeline" => {
+            let (playlists, others): (Vec<_>, Vec<_>) = container
+                .timeline
+                .into_iter()
+                .partition(|entry| entry.is_playlist());
+            let mut notifications = vec![];
+            if !playlists.is_empty() {
+                notifications.push(Notification::Playlist(playlists));
+            }
+            if !others.is_empty() {
+                notifications.push(Notification::Timeline(others));
+            }
+            notifications
+        }
+        "activity" => vec![Notification::Activity(container.activities)],
+        "playing" => vec![Notification::Playing(container.play_sessions)],
+        other => vec![Notification::Unknown(other.into())],
+    };
+
+    Ok(notifications)
+}
+
+fn set_read_timeout(socket: &Socket, timeout: Duration) -> io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref().set_read_timeout(Some(timeout)),
+        _ => Ok(()),
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Stream of notifications from the Plex server, connected with the HTTP settings of the
+/// configuration. Lost connections are reported as `ConnectionFailed` and re-established
+/// after a short delay, messages that can't be decoded as `InvalidMessage`. The stream ends
+/// after the server rejected the token or if the HTTP settings can't be used.
+pub struct NotificationStream {
+    url: String,
+    http: HttpConfig,
+    socket: Option<Socket>,
+    pending: VecDeque<Notification>,
+    /// Whether a ping was sent and the server stayed silent since
+    awaiting_pong: bool,
+    /// Whether the connection was lost, it is re-established after `RECONNECT_DELAY`
+    reconnecting: bool,
+    finished: bool,
+}
+
+impl NotificationStream {
+    pub fn new(config: &PlexConfig) -> Self {
+        Self {
+            url: config.get_notifications_url(),
+            http: config.http.clone(),
+            socket: None,
+            pending: VecDeque::new(),
+            awaiting_pong: false,
+            reconnecting: false,
+            finished: false,
+        }
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        let socket = websocket::connect(&self.url, &self.http)?;
+        set_read_timeout(&socket, PING_INTERVAL)
+            .map_err(|e| Error::connection_failed(tungstenite::Error::Io(e)))?;
+        self.socket = Some(socket);
+        self.awaiting_pong = false;
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> Result<Message, Error> {
+        if self.socket.is_none() {
+            self.connect()?;
+        }
+
+        let socket = self.socket.as_mut().unwrap();
+        loop {
+            match socket.read_message() {
+                Ok(message) => {
+                    self.awaiting_pong = false;
+                    return Ok(message);
+                }
+                Err(tungstenite::Error::Io(e)) if is_timeout(&e) => {
+                    if self.awaiting_pong {
+                        return Err(Error::connection_failed(tungstenite::Error::Io(e)));
+                    }
+                    socket
+                        .write_message(Message::Ping(vec![]))
+                        .map_err(Error::connection_failed)?;
+                    self.awaiting_pong = true;
+                }
+                Err(e) => return Err(Error::connection_failed(e)),
+            }
+        }
+    }
+}
+
+impl Iterator for NotificationStream {
+    type Item = Result<Notification, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(notification) = self.pending.pop_front() {
+                return Some(Ok(notification));
+            }
+            if self.finished {
+                return None;
+            }
+            if self.reconnecting {
+                thread::sleep(RECONNECT_DELAY);
+                self.reconnecting = false;
+            }
+
+            match self.read_message() {
+                Ok(Message::Text(text)) => match decode(&text) {
+                    Ok(notifications) => self.pending.extend(notifications),
+                    Err(e) => return Some(Err(e)),
+                },
+                Ok(Message::Close(_)) => {
+                    self.socket = None;
+                    self.reconnecting = true;
+                }
+                Ok(_) => {}
+                Err(e @ (Error::Unauthorized | Error::InvalidHttpConfig(_))) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+                Err(e) => {
+                    self.socket = None;
+                    self.reconnecting = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Listens for notifications on a background thread and passes each of them, or the error
+/// that interrupted the stream, to `handler`. The thread ends when the stream does.
+pub fn subscribe<F>(config: PlexConfig, mut handler: F) -> thread::JoinHandle<()>
+where
+    F: FnMut(Result<Notification, Error>) + Send + 'static,
+{
+    thread::spawn(move || {
+        for notification in NotificationStream::new(&config) {
+            handler(notification);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_playlist_entries_from_other_timeline_entries() {
+        let message = r#"{"NotificationContainer":{"type":"timeline","size":3,"TimelineEntry":[
+            {"identifier":"com.plexapp.plugins.library","sectionID":"-1","itemID":"101",
+             "type":15,"title":"Road Trip","state":5,"updatedAt":1672531200},
+            {"identifier":"com.plexapp.plugins.library","sectionID":"3","itemID":"2001",
+             "type":10,"title":"Intro","state":0},
+            {"identifier":"com.plexapp.plugins.library","sectionID":"-1","itemID":"102",
+             "type":15,"state":9}
+        ]}}"#;
+
+        let notifications = decode(message).unwrap();
+        assert_eq!(notifications.len(), 2);
+        match &notifications[0] {
+            Notification::Playlist(entries) => {
+                assert_eq!(entries.len(), 2);
+                assert_eq!(entries[0].item_id, "101");
+                assert_eq!(entries[0].title.as_deref(), Some("Road Trip"));
+                assert_eq!(entries[0].state, TimelineState::Finished);
+                assert_eq!(entries[0].updated_at, Some(1672531200));
+                assert_eq!(entries[1].item_id, "102");
+                assert_eq!(entries[1].title, None);
+                assert_eq!(entries[1].state, TimelineState::Deleted);
+            }
+            other => panic!("expected playlist entries, got {:?}", other),
+        }
+        match &notifications[1] {
+            Notification::Timeline(entries) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].item_id, "2001");
+                assert_eq!(entries[0].metadata_type, 10);
+                assert_eq!(entries[0].state, TimelineState::Created);
+            }
+            other => panic!("expected timeline entries, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn timeline_without_playlists_has_no_playlist_notification() {
+        let message = r#"{"NotificationContainer":{"type":"timeline","size":1,"TimelineEntry":[
+            {"itemID":"2001","type":10,"state":7}
+        ]}}"#;
+
+        let notifications = decode(message).unwrap();
+        assert_eq!(notifications.len(), 1);
+        match &notifications[0] {
+            Notification::Timeline(entries) => {
+                assert_eq!(entries[0].state, TimelineState::Other(7));
+            }
+            other => panic!("expected timeline entries, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_activities() {
+        let message = r#"{"NotificationContainer":{"type":"activity","size":1,"ActivityNotification":[
+            {"event":"updated","uuid":"4c2a5c1e-7f43-4bd4-9e3c-2f3c0ed1f9a1","Activity":{
+                "uuid":"4c2a5c1e-7f43-4bd4-9e3c-2f3c0ed1f9a1","type":"library.update.section",
+                "cancellable":false,"userID":1,"title":"Scanning Music",
+                "subtitle":"Road Trip","progress":42}},
+            {"event":"paused","uuid":"d1e2","Activity":{"type":"media.generate","title":"Thumbnails"}}
+        ]}}"#;
+
+        let notifications = decode(message).unwrap();
+        let expected = vec![
+            ActivityNotification {
+                event: ActivityEvent::Updated,
+                uuid: "4c2a5c1e-7f43-4bd4-9e3c-2f3c0ed1f9a1".into(),
+                activity: Activity {
+                    activity_type: "library.update.section".into(),
+                    title: "Scanning Music".into(),
+                    subtitle: Some("Road Trip".into()),
+                    progress: Some(42),
+                },
+            },
+            ActivityNotification {
+                event: ActivityEvent::Other,
+                uuid: "d1e2".into(),
+                activity: Activity {
+                    activity_type: "media.generate".into(),
+                    title: "Thumbnails".into(),
+                    subtitle: None,
+                    progress: None,
+                },
+            },
+        ];
+        assert_eq!(notifications, vec![Notification::Activity(expected)]);
+    }
+
+    #[test]
+    fn decodes_play_sessions() {
+        let message = r#"{"NotificationContainer":{"type":"playing","size":1,"PlaySessionStateNotification":[
+            {"sessionKey":"12","clientIdentifier":"abc","guid":"","ratingKey":"2001",
+             "url":"","key":"/library/metadata/2001","viewOffset":61000,"playQueueItemID":7,
+             "state":"playing"}
+        ]}}"#;
+
+        let notifications = decode(message).unwrap();
+        let expected = PlaySessionStateNotification {
+            session_key: "12".into(),
+            rating_key: "2001".into(),
+            state: "playing".into(),
+            view_offset: Some(61000),
+        };
+        assert_eq!(notifications, vec![Notification::Playing(vec![expected])]);
+    }
+
+    #[test]
+    fn unknown_types_are_reported_by_name() {
+        let message = r#"{"NotificationContainer":{"type":"update.statechange","size":1,
+            "AutoUpdateNotification":[{"key":"","version":"1.30.0"}]}}"#;
+
+        let notifications = decode(message).unwrap();
+        assert_eq!(
+            notifications,
+            vec![Notification::Unknown("update.statechange".into())]
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_invalid() {
+        for message in [
+            "not json",
+            r#"{"type":"timeline"}"#,
+            r#"{"NotificationContainer":{"type":"timeline","TimelineEntry":[{"itemID":"1"}]}}"#,
+        ] {
+            assert!(matches!(decode(message), Err(Error::InvalidMessage(_))));
+        }
+    }
+}
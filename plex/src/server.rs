@@ -0,0 +1,159 @@
+use std::fmt::{Display, Formatter};
+
+use crate::config::PlexConfig;
+use crate::{fetch, Error};
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "MediaContainer")]
+struct Identity {
+    #[serde(rename = "machineIdentifier")]
+    machine_identifier: String,
+    version: String,
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+#[serde(rename = "MediaContainer")]
+struct ServerRoot {
+    #[serde(rename = "friendlyName")]
+    friendly_name: String,
+    #[serde(rename = "myPlexUsername", default)]
+    owner: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStatus {
+    pub name: Option<String>,
+    pub version: String,
+    pub machine_identifier: String,
+    pub owner: Option<String>,
+    pub has_access: bool,
+}
+
+impl Display for ServerStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Server: {}", self.name.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Machine identifier: {}", self.machine_identifier)?;
+        writeln!(f, "Owner: {}", self.owner.as_deref().unwrap_or("unknown"))?;
+        write!(
+            f,
+            "Token access: {}",
+            if self.has_access { "✔" } else { "❌" }
+        )
+    }
+}
+
+/// Queries the identity of the server and checks whether the configured token grants access.
+/// The identity endpoint doesn't require a token, so a reachable server with a wrong token
+/// still returns a status, with `has_access` set to false.
+pub fn fetch_status(config: &PlexConfig) -> Result<ServerStatus, Error> {
+    let identity: Identity = fetch(config, config.get_identity_url())?;
+
+    let root: Option<ServerRoot> = match fetch(config, config.get_root_url()) {
+        Ok(root) => Some(root),
+        Err(Error::RequestFailed(downloader::Error::Unauthorized { .. })) => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(ServerStatus {
+        name: root.as_ref().map(|r| r.friendly_name.clone()),
+        version: identity.version,
+        machine_identifier: identity.machine_identifier,
+        owner: root.as_ref().and_then(|r| r.owner.clone()),
+        has_access: root.is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use downloader::test_support::serve_each;
+
+    use super::*;
+    use crate::format::tests::parse_fixture;
+    use crate::format::ResponseFormat;
+
+    /// A response of the server with the fixture `<name>.xml` as body
+    fn fixture_response(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("{}.xml", name));
+        let body = fs::read_to_string(path).unwrap();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml;charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    const UNAUTHORIZED: &str =
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 12\r\nConnection: close\r\n\r\nUnauthorized";
+
+    /// Fetches the status from a server answering the identity and root requests in turn
+    fn status_of_server(responses: Vec<String>) -> Result<ServerStatus, Error> {
+        let (address, _) = serve_each(responses);
+        let config = PlexConfig {
+            url: format!("http://{}", address),
+            token: "token".into(),
+            ..Default::default()
+        };
+        fetch_status(&config)
+    }
+
+    #[test]
+    fn reachable_server_with_access() {
+        let status =
+            status_of_server(vec![fixture_response("identity"), fixture_response("root")]).unwrap();
+        assert_eq!(
+            status,
+            ServerStatus {
+                name: Some("Living Room".into()),
+                version: "1.30.1.6562-915986d62".into(),
+                machine_identifier: "0123456789abcdef0123456789abcdef01234567".into(),
+                owner: Some("owner@example.com".into()),
+                has_access: true,
+            }
+        );
+    }
+
+    #[test]
+    fn reachable_server_without_access() {
+        let status =
+            status_of_server(vec![fixture_response("identity"), UNAUTHORIZED.into()]).unwrap();
+        assert!(!status.has_access);
+        assert_eq!(status.name, None);
+        assert_eq!(status.owner, None);
+        assert_eq!(status.version, "1.30.1.6562-915986d62");
+    }
+
+    #[test]
+    fn unauthorized_identity_is_an_error() {
+        assert!(matches!(
+            status_of_server(vec![UNAUTHORIZED.into()]),
+            Err(Error::RequestFailed(downloader::Error::Unauthorized { .. }))
+        ));
+    }
+
+    #[test]
+    fn parses_identity() {
+        let xml: Identity = parse_fixture("identity", ResponseFormat::Xml);
+        let json: Identity = parse_fixture("identity", ResponseFormat::Json);
+        assert_eq!(xml, json);
+        assert_eq!(
+            xml.machine_identifier,
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert_eq!(xml.version, "1.30.1.6562-915986d62");
+    }
+
+    #[test]
+    fn parses_root() {
+        let xml: ServerRoot = parse_fixture("root", ResponseFormat::Xml);
+        let json: ServerRoot = parse_fixture("root", ResponseFormat::Json);
+        assert_eq!(xml, json);
+        assert_eq!(xml.friendly_name, "Living Room");
+        assert_eq!(xml.owner.as_deref(), Some("owner@example.com"));
+    }
+}
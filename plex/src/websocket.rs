@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use downloader::HttpConfig;
+use native_tls::{Certificate, TlsConnector};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::error::UrlError;
+use tungstenite::handshake::HandshakeError;
+use tungstenite::http::Uri;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Connector, WebSocket};
+
+use crate::notifications::Error;
+
+pub(crate) type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn io_error(error: io::Error) -> Error {
+    Error::connection_failed(tungstenite::Error::Io(error))
+}
+
+fn connect_error(error: tungstenite::Error) -> Error {
+    match &error {
+        tungstenite::Error::Http(response) if matches!(response.status().as_u16(), 401 | 403) => {
+            Error::Unauthorized
+        }
+        _ => Error::connection_failed(error),
+    }
+}
+
+/// Host and port of `uri`, the port defaults to the one of its scheme
+fn host_and_port(uri: &Uri) -> Option<(String, u16)> {
+    let default_port = match uri.scheme_str()? {
+        "wss" | "https" => 443,
+        "ws" | "http" => 80,
+        _ => return None,
+    };
+    Some((uri.host()?.into(), uri.port_u16().unwrap_or(default_port)))
+}
+
+/// Connects to the first address of `host` that accepts the connection
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host has no address");
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Opens a tunnel to `host` with a CONNECT request to the proxy. Only `http://` proxies
+/// without credentials are supported, other proxies are rejected as `InvalidHttpConfig`.
+fn connect_through_proxy(
+    proxy: &str,
+    host: &str,
+    port: u16,
+    http: &HttpConfig,
+) -> Result<TcpStream, Error> {
+    let unsupported = || Error::InvalidHttpConfig(format!("unsupported proxy {}", proxy));
+    let uri: Uri = proxy.parse().map_err(|_| unsupported())?;
+    let has_credentials = uri.authority().is_some_and(|a| a.as_str().contains('@'));
+    if uri.scheme_str() != Some("http") || has_credentials {
+        return Err(unsupported());
+    }
+    let (proxy_host, proxy_port) = host_and_port(&uri).ok_or_else(unsupported)?;
+
+    let connect_timeout = Duration::from_secs(http.connect_timeout_secs);
+    let mut stream = connect_tcp(&proxy_host, proxy_port, connect_timeout).map_err(io_error)?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(http.read_timeout_secs)))
+        .map_err(io_error)?;
+    write!(
+        stream,
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n",
+        host = host,
+        port = port
+    )
+    .map_err(io_error)?;
+
+    // Nothing follows the response until the tunneled connection is used
+    let mut reader = BufReader::new(&stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).map_err(io_error)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header).map_err(io_error)? > 2 {
+        header.clear();
+    }
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        let message = format!("proxy answered {}", status_line.trim());
+        return Err(io_error(io::Error::other(message)));
+    }
+    Ok(stream)
+}
+
+/// TLS settings for the configured server, so invalid certificates are accepted if
+/// the configuration allows it
+fn tls_connector(http: &HttpConfig) -> Result<TlsConnector, Error> {
+    let invalid = |path: &std::path::Path, error: String| {
+        Error::InvalidHttpConfig(format!("root certificate {:?}: {}", path, error))
+    };
+    let mut builder = TlsConnector::builder();
+    builder.danger_accept_invalid_certs(http.accept_invalid_certs);
+    for path in &http.root_certificates {
+        let pem = fs::read(path).map_err(|e| invalid(path, e.to_string()))?;
+        let certificate = Certificate::from_pem(&pem).map_err(|e| invalid(path, e.to_string()))?;
+        builder.add_root_certificate(certificate);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::InvalidHttpConfig(e.to_string()))
+}
+
+/// Opens the websocket at `url` with the timeouts, proxy and certificates of `http`.
+/// The read timeout of the returned socket is the one of `http`.
+pub(crate) fn connect(url: &str, http: &HttpConfig) -> Result<Socket, Error> {
+    let request = url
+        .into_client_request()
+        .map_err(Error::connection_failed)?;
+    let (host, port) = host_and_port(request.uri()).ok_or_else(|| {
+        Error::connection_failed(tungstenite::Error::Url(UrlError::UnsupportedUrlScheme))
+    })?;
+    let connector = match request.uri().scheme_str() {
+        Some("wss") => Some(Connector::NativeTls(tls_connector(http)?)),
+        _ => None,
+    };
+
+    let stream = match &http.proxy {
+        Some(proxy) => connect_through_proxy(proxy, &host, port, http)?,
+        None => connect_tcp(&host, port, Duration::from_secs(http.connect_timeout_secs))
+            .map_err(io_error)?,
+    };
+    let timeout = Some(Duration::from_secs(http.read_timeout_secs));
+    stream.set_read_timeout(timeout).map_err(io_error)?;
+    stream.set_write_timeout(timeout).map_err(io_error)?;
+
+    match tungstenite::client_tls_with_config(request, stream, None, connector) {
+        Ok((socket, _)) => Ok(socket),
+        Err(HandshakeError::Failure(e)) => Err(connect_error(e)),
+        // Only happens when the server doesn't answer within the read timeout
+        Err(HandshakeError::Interrupted(_)) => Err(io_error(io::ErrorKind::TimedOut.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use downloader::test_support::serve_once;
+
+    use super::*;
+
+    /// A proxy on localhost that answers the CONNECT request with `response`
+    fn fake_proxy(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let (address, handle) = serve_once(response);
+        (format!("http://{}", address), handle)
+    }
+
+    #[test]
+    fn tunnels_through_http_proxy() {
+        let (proxy, handle) = fake_proxy("HTTP/1.1 200 Connection established\r\n\r\n");
+        let result = connect_through_proxy(&proxy, "plex.local", 32400, &HttpConfig::default());
+        assert!(result.is_ok());
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("CONNECT plex.local:32400 HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn refused_tunnel_is_a_connection_failure() {
+        let (proxy, handle) = fake_proxy("HTTP/1.1 403 Forbidden\r\nVia: proxy\r\n\r\n");
+        let result = connect_through_proxy(&proxy, "plex.local", 32400, &HttpConfig::default());
+        assert!(matches!(result, Err(Error::ConnectionFailed(_))));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn default_ports_depend_on_the_scheme() {
+        let port = |url: &str| host_and_port(&url.parse().unwrap()).map(|(_, port)| port);
+        assert_eq!(port("wss://plex.local/"), Some(443));
+        assert_eq!(port("ws://plex.local/"), Some(80));
+        assert_eq!(port("ws://192.168.1.2:32400/"), Some(32400));
+        assert_eq!(port("http://proxy:3128"), Some(3128));
+        assert_eq!(port("socks5://proxy:1080"), None);
+    }
+
+    #[test]
+    fn only_plain_http_proxies_are_supported() {
+        let http = HttpConfig::default();
+        for proxy in [
+            "socks5://proxy:1080",
+            "https://proxy",
+            "http://user:pw@proxy",
+        ] {
+            let result = connect_through_proxy(proxy, "plex.local", 32400, &http);
+            assert!(matches!(result, Err(Error::InvalidHttpConfig(_))));
+        }
+    }
+}